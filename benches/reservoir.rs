@@ -0,0 +1,197 @@
+/*!
+Benchmarks of the core reservoir operations.
+
+Run with `cargo bench`, optionally passing a substring to filter
+the benchmarks by name, e.g. `cargo bench -- merge`.
+
+This is a minimal harness based on `std::time`, reporting the median
+time per iteration over a few runs, so that performance-motivated
+changes to the reservoir layout can be compared without extra dependencies.
+!*/
+
+use rand::{Rng as _, SeedableRng as _};
+use std::{
+    hint::black_box,
+    time::{Duration, Instant},
+};
+
+struct Bencher {
+    filter: Option<String>,
+}
+
+impl Bencher {
+    const RUNS: usize = 11;
+
+    fn run(&self, name: &str, iterations: u32, mut fun: impl FnMut()) {
+        if let Some(ref filter) = self.filter {
+            if !name.contains(filter.as_str()) {
+                return;
+            }
+        }
+        // warm up the caches and the branch predictor
+        for _ in 0..iterations / 10 {
+            fun();
+        }
+        let mut times = (0..Self::RUNS)
+            .map(|_| {
+                let start = Instant::now();
+                for _ in 0..iterations {
+                    fun();
+                }
+                start.elapsed() / iterations
+            })
+            .collect::<Vec<Duration>>();
+        times.sort();
+        println!(
+            "{:<32} {:>10.1} ns/iter (min {:.1}, max {:.1})",
+            name,
+            times[Self::RUNS / 2].as_secs_f64() * 1e9,
+            times[0].as_secs_f64() * 1e9,
+            times[Self::RUNS - 1].as_secs_f64() * 1e9,
+        );
+    }
+}
+
+fn bench_stream(bencher: &Bencher) {
+    let mut random = rand::rngs::StdRng::seed_from_u64(0);
+    let targets = (0..32).map(|_| random.gen::<f32>()).collect::<Vec<_>>();
+    bencher.run("stream/32", 10_000, || {
+        let mut builder = rs_voir::ReservoirBuilder::default();
+        for &target in targets.iter() {
+            black_box(builder.stream(0.5, target, (), &mut random));
+        }
+        black_box(builder.finish());
+    });
+}
+
+fn bench_stream_iter(bencher: &Bencher) {
+    let mut random = rand::rngs::StdRng::seed_from_u64(0);
+    let targets = (0..32).map(|_| random.gen::<f32>()).collect::<Vec<_>>();
+    bencher.run("stream_iter/32", 10_000, || {
+        let mut builder = rs_voir::ReservoirBuilder::default();
+        let candidates = targets.iter().map(|&target| (0.5, target));
+        black_box(builder.stream_iter(candidates, &mut random));
+        black_box(builder.finish());
+    });
+}
+
+fn bench_stream_batch(bencher: &Bencher) {
+    let mut random = rand::rngs::StdRng::seed_from_u64(0);
+    let targets = (0..32).map(|_| random.gen::<f32>()).collect::<Vec<_>>();
+    bencher.run("stream_batch/32", 10_000, || {
+        let mut builder = rs_voir::ReservoirBuilder::default();
+        for chunk in targets.chunks_exact(8) {
+            let target_values: [f32; 8] = chunk.try_into().unwrap();
+            black_box(builder.stream_batch([0.5; 8], target_values, [(); 8], &mut random));
+        }
+        black_box(builder.finish());
+    });
+}
+
+fn bench_merge(bencher: &Bencher) {
+    let mut random = rand::rngs::StdRng::seed_from_u64(0);
+    let others = (0..32)
+        .map(|_| {
+            let reservoir = rs_voir::Reservoir::from_sample(random.gen_range(0.1..1.0), ());
+            reservoir.to_builder(random.gen())
+        })
+        .collect::<Vec<_>>();
+    bencher.run("merge/32", 10_000, || {
+        let mut builder = rs_voir::ReservoirBuilder::default();
+        for other in others.iter() {
+            black_box(builder.merge(other, &mut random));
+        }
+        black_box(builder.finish());
+    });
+}
+
+/// Spatial reuse over a whole grid, merging the 4 direct neighbors of every cell.
+fn bench_spatial_pass(bencher: &Bencher) {
+    const WIDTH: usize = 64;
+    const HEIGHT: usize = 64;
+    let mut random = rand::rngs::StdRng::seed_from_u64(0);
+    let targets = (0..WIDTH * HEIGHT)
+        .map(|_| random.gen::<f32>())
+        .collect::<Vec<_>>();
+    let mut reservoirs = targets
+        .iter()
+        .map(|_| rs_voir::Reservoir::from_sample(random.gen_range(0.1..1.0), ()))
+        .collect::<Vec<_>>();
+    let mut output = reservoirs.clone();
+
+    bencher.run("spatial_pass/64x64", 20, || {
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let index = y * WIDTH + x;
+                let mut builder = reservoirs[index].to_builder(targets[index]);
+                let neighbors = [
+                    (x > 0).then(|| index - 1),
+                    (x + 1 < WIDTH).then(|| index + 1),
+                    (y > 0).then(|| index - WIDTH),
+                    (y + 1 < HEIGHT).then(|| index + WIDTH),
+                ];
+                for other_index in neighbors.into_iter().flatten() {
+                    let other = reservoirs[other_index]
+                        .with_max_history(20)
+                        .to_builder(targets[other_index]);
+                    builder.merge(&other, &mut random);
+                }
+                output[index] = builder.finish();
+            }
+        }
+        std::mem::swap(&mut reservoirs, &mut output);
+    });
+}
+
+/// Same as `bench_spatial_pass`, but merging all the neighbors at once.
+fn bench_spatial_pass_many(bencher: &Bencher) {
+    const WIDTH: usize = 64;
+    const HEIGHT: usize = 64;
+    let mut random = rand::rngs::StdRng::seed_from_u64(0);
+    let targets = (0..WIDTH * HEIGHT)
+        .map(|_| random.gen::<f32>())
+        .collect::<Vec<_>>();
+    let mut reservoirs = targets
+        .iter()
+        .map(|_| rs_voir::Reservoir::from_sample(random.gen_range(0.1..1.0), ()))
+        .collect::<Vec<_>>();
+    let mut output = reservoirs.clone();
+    let mut others = Vec::with_capacity(4);
+
+    bencher.run("spatial_pass_many/64x64", 20, || {
+        for y in 0..HEIGHT {
+            for x in 0..WIDTH {
+                let index = y * WIDTH + x;
+                let mut builder = reservoirs[index].to_builder(targets[index]);
+                let neighbors = [
+                    (x > 0).then(|| index - 1),
+                    (x + 1 < WIDTH).then(|| index + 1),
+                    (y > 0).then(|| index - WIDTH),
+                    (y + 1 < HEIGHT).then(|| index + WIDTH),
+                ];
+                others.clear();
+                others.extend(neighbors.into_iter().flatten().map(|other_index| {
+                    reservoirs[other_index]
+                        .with_max_history(20)
+                        .to_builder(targets[other_index])
+                }));
+                builder.merge_many(&others, &mut random);
+                output[index] = builder.finish();
+            }
+        }
+        std::mem::swap(&mut reservoirs, &mut output);
+    });
+}
+
+fn main() {
+    let bencher = Bencher {
+        // skip the "--bench" flag passed by cargo
+        filter: std::env::args().skip(1).find(|arg| !arg.starts_with("--")),
+    };
+    bench_stream(&bencher);
+    bench_stream_iter(&bencher);
+    bench_stream_batch(&bencher);
+    bench_merge(&bencher);
+    bench_spatial_pass(&bencher);
+    bench_spatial_pass_many(&bencher);
+}
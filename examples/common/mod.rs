@@ -0,0 +1,47 @@
+//! Helpers shared between the terminal UI examples.
+
+/// Terminal switched into the alternate screen, restored on drop.
+pub struct Output {
+    pub terminal: tui::Terminal<tui::backend::CrosstermBackend<std::io::Stdout>>,
+}
+
+impl Output {
+    pub fn grab() -> Result<Self, std::io::Error> {
+        use crossterm::ExecutableCommand as _;
+
+        let mut stdout = std::io::stdout();
+        stdout.execute(crossterm::terminal::EnterAlternateScreen)?;
+        stdout.execute(crossterm::event::EnableMouseCapture)?;
+        crossterm::terminal::enable_raw_mode()?;
+
+        let backend = tui::backend::CrosstermBackend::new(stdout);
+        let mut terminal = tui::Terminal::new(backend)?;
+        terminal.hide_cursor()?;
+        Ok(Self { terminal })
+    }
+
+    fn release(&mut self) -> Result<(), std::io::Error> {
+        use crossterm::ExecutableCommand as _;
+
+        if std::thread::panicking() {
+            // give the opportunity to see the result
+            let _ = crossterm::event::read();
+        }
+
+        crossterm::terminal::disable_raw_mode()?;
+        self.terminal
+            .backend_mut()
+            .execute(crossterm::event::DisableMouseCapture)?;
+        self.terminal
+            .backend_mut()
+            .execute(crossterm::terminal::LeaveAlternateScreen)?;
+        self.terminal.show_cursor()?;
+        Ok(())
+    }
+}
+
+impl Drop for Output {
+    fn drop(&mut self) {
+        let _ = self.release();
+    }
+}
@@ -0,0 +1,386 @@
+/*!
+Volumetric fog example.
+
+The 2D world consists of:
+    - ground, with a vertical camera ray shot up from every cell
+    - homogeneous fog layer covering the bottom part of the world
+    - point light (the lamp) inside or above the fog
+    - occluder (a line segment) casting light shafts into the fog
+
+Every ray gathers the light in-scattered by the fog along its length.
+Candidates are distances along the ray, produced either by distance sampling
+(proportional to the transmittance) or by equiangular sampling (proportional
+to the inverse squared distance to the lamp). RIS resamples them by
+the in-scattered light, testing every candidate for visibility.
+
+Two pixel buffers are updated side by side: one with RIS alone, and one
+that also reuses the distance samples temporally and from neighboring rays.
+Both are compared against a reference computed by dense quadrature.
+
+Keys: `e` switches the candidate distribution, `,`/`.` change the number
+of candidates, `Esc` quits.
+!*/
+
+use std::{f32::consts::PI, ops::Range, time::Duration};
+
+mod common;
+use common::Output;
+
+struct WorldConfig {
+    surface_length: u16,
+    fog_height: f32,
+    fog_density: f32,
+    lamp_position: glam::Vec2,
+    lamp_intensity: f32,
+    occluder_y: f32,
+    occluder_x: Range<f32>,
+}
+
+impl WorldConfig {
+    /// Transmittance between two points.
+    fn transmittance(&self, a: glam::Vec2, b: glam::Vec2) -> f32 {
+        let (low, high) = if a.y < b.y { (a.y, b.y) } else { (b.y, a.y) };
+        let inside = high.min(self.fog_height) - low.max(0.0);
+        if inside <= 0.0 {
+            return 1.0;
+        }
+        // fraction of the segment inside the fog layer
+        let fraction = if high > low {
+            inside / (high - low)
+        } else {
+            1.0
+        };
+        (-self.fog_density * fraction * a.distance(b)).exp()
+    }
+
+    fn check_visibility(&self, point: glam::Vec2) -> bool {
+        let t = (self.occluder_y - point.y) / (self.lamp_position.y - point.y);
+        if !(0.0..1.0).contains(&t) {
+            return true;
+        }
+        let x = point.x + (self.lamp_position.x - point.x) * t;
+        !self.occluder_x.contains(&x)
+    }
+
+    /// Light in-scattered towards the camera ray origin at a distance along the ray,
+    /// ignoring the occluder.
+    fn unshadowed_inscattering(&self, origin: glam::Vec2, distance: f32) -> f32 {
+        let point = origin + glam::vec2(0.0, distance);
+        let lamp_distance = point.distance(self.lamp_position);
+        // isotropic phase function in 2D
+        let phase = 0.5 / PI;
+        // the lamp intensity falls off linearly with distance in 2D
+        let incoming = self.lamp_intensity * self.transmittance(point, self.lamp_position)
+            / lamp_distance.max(1e-3);
+        self.transmittance(origin, point) * self.fog_density * phase * incoming
+    }
+
+    fn inscattering(&self, origin: glam::Vec2, distance: f32) -> f32 {
+        let point = origin + glam::vec2(0.0, distance);
+        if self.check_visibility(point) {
+            self.unshadowed_inscattering(origin, distance)
+        } else {
+            0.0
+        }
+    }
+
+    fn ray_origin(&self, cell_index: usize) -> glam::Vec2 {
+        glam::vec2(cell_index as f32 + 0.5, 0.0)
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum CandidateDistribution {
+    /// Proportional to the transmittance along the ray.
+    Distance,
+    /// Proportional to the inverse squared distance to the lamp.
+    Equiangular,
+}
+
+impl CandidateDistribution {
+    /// Generate a distance along the ray, returning it with its PDF.
+    fn sample(
+        self,
+        world: &WorldConfig,
+        origin: glam::Vec2,
+        random: &mut impl rand::Rng,
+    ) -> (f32, f32) {
+        let length = world.fog_height;
+        let u = random.gen::<f32>();
+        match self {
+            Self::Distance => {
+                let sigma = world.fog_density;
+                let norm = 1.0 - (-sigma * length).exp();
+                let t = -(1.0 - u * norm).ln() / sigma;
+                (t, sigma * (-sigma * t).exp() / norm)
+            }
+            Self::Equiangular => {
+                // closest point to the lamp along the ray
+                let delta = world.lamp_position.y - origin.y;
+                let d = (world.lamp_position.x - origin.x).abs().max(1e-3);
+                let theta_a = (-delta / d).atan();
+                let theta_b = ((length - delta) / d).atan();
+                let theta = theta_a + u * (theta_b - theta_a);
+                let t = (delta + d * theta.tan()).clamp(0.0, length);
+                let x = t - delta;
+                (t, d / ((theta_b - theta_a) * (d * d + x * x)))
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct Pixel {
+    /// Reservoir of the distances along the ray.
+    reservoir: rs_voir::Reservoir<f32>,
+    value: f32,
+}
+
+struct RestirConfig {
+    distribution: CandidateDistribution,
+    initial_samples: u32,
+    max_temporal_history: u32,
+    max_spatial_history: u32,
+}
+
+/// A buffer of pixels updated with a given reuse configuration.
+struct Layer {
+    pixels: Box<[Pixel]>,
+    reuse: bool,
+    smooth_error: f32,
+}
+
+impl Layer {
+    fn new(surface_length: u16, reuse: bool) -> Self {
+        Self {
+            pixels: (0..surface_length).map(|_| Pixel::default()).collect(),
+            reuse,
+            smooth_error: 0.0,
+        }
+    }
+
+    fn update(
+        &mut self,
+        world: &WorldConfig,
+        restir: &RestirConfig,
+        reference: &[f32],
+        random: &mut impl rand::Rng,
+    ) {
+        let backup = self
+            .pixels
+            .iter()
+            .map(|pixel| pixel.reservoir.clone())
+            .collect::<Vec<_>>();
+
+        for (cell_index, pixel) in self.pixels.iter_mut().enumerate() {
+            let origin = world.ray_origin(cell_index);
+            let mut builder = rs_voir::ReservoirBuilder::default();
+
+            for _ in 0..restir.initial_samples {
+                let (distance, source_pdf) = restir.distribution.sample(world, origin, random);
+                let target_value = world.inscattering(origin, distance);
+                builder.stream(source_pdf, target_value, distance, random);
+            }
+
+            if self.reuse {
+                let mut sources = vec![(cell_index, restir.max_temporal_history)];
+                for offset in [-1, 1] {
+                    let index = cell_index as isize + offset;
+                    if index >= 0 && index < world.surface_length as isize {
+                        sources.push((index as usize, restir.max_spatial_history));
+                    }
+                }
+                for (index, max_history) in sources {
+                    // the rays are parallel, so distances map to the same height
+                    // with a unit Jacobian
+                    let prev = backup[index].with_max_history(max_history);
+                    let target_value = world.inscattering(origin, *prev.sample());
+                    if prev.has_weight() && target_value > 0.0 {
+                        builder.merge(&prev.to_builder(target_value), random);
+                    } else {
+                        builder.merge_history(&prev);
+                    }
+                }
+            }
+
+            pixel.reservoir = builder.finish();
+            pixel.value = world.inscattering(origin, *pixel.reservoir.sample())
+                * pixel.reservoir.contribution_weight();
+        }
+
+        let sum_error = self
+            .pixels
+            .iter()
+            .zip(reference)
+            .map(|(pixel, &expected)| (pixel.value - expected) * (pixel.value - expected))
+            .sum::<f32>();
+        let rmse = (sum_error / self.pixels.len() as f32).sqrt();
+        let accumulation = 0.05;
+        self.smooth_error = self.smooth_error * (1.0 - accumulation) + accumulation * rmse;
+    }
+}
+
+struct Render {
+    world: WorldConfig,
+    restir: RestirConfig,
+    reference: Vec<f32>,
+    layers: [Layer; 2],
+    random: rand::rngs::ThreadRng,
+    frame_index: usize,
+}
+
+impl Render {
+    fn compute_reference(world: &WorldConfig, step_count: u32) -> Vec<f32> {
+        let step = world.fog_height / step_count as f32;
+        (0..world.surface_length as usize)
+            .map(|cell_index| {
+                let origin = world.ray_origin(cell_index);
+                (0..step_count)
+                    .map(|i| world.inscattering(origin, (i as f32 + 0.5) * step))
+                    .sum::<f32>()
+                    * step
+            })
+            .collect()
+    }
+
+    fn update(&mut self) {
+        self.frame_index += 1;
+        for layer in self.layers.iter_mut() {
+            layer.update(&self.world, &self.restir, &self.reference, &mut self.random);
+        }
+    }
+
+    fn draw<B: tui::backend::Backend>(&self, frame: &mut tui::Frame<B>) {
+        use tui::{
+            layout as l,
+            style::{Color, Style},
+            text::{Span, Spans},
+            widgets as w,
+        };
+
+        let scale = 100.0;
+        let max_value = self.reference.iter().cloned().fold(0.0, f32::max) * 2.0;
+        let to_bars = |values: &mut dyn Iterator<Item = f32>| {
+            values
+                .map(|value| (value.max(0.0) * scale) as u64)
+                .collect::<Vec<_>>()
+        };
+
+        let hor_rects = l::Layout::default()
+            .direction(l::Direction::Horizontal)
+            .constraints(
+                [
+                    l::Constraint::Length((self.world.surface_length + 4) as _),
+                    l::Constraint::Length(32),
+                ]
+                .as_ref(),
+            )
+            .margin(1)
+            .split(frame.size());
+        let left_rects = l::Layout::default()
+            .direction(l::Direction::Vertical)
+            .constraints([l::Constraint::Ratio(1, 3); 3].as_ref())
+            .split(hor_rects[0]);
+
+        let mut rows = vec![(
+            "Reference".to_string(),
+            to_bars(&mut self.reference.iter().cloned()),
+        )];
+        for layer in self.layers.iter() {
+            let title = if layer.reuse { "ReSTIR" } else { "RIS only" };
+            rows.push((
+                format!("{} (error {:.3})", title, layer.smooth_error),
+                to_bars(&mut layer.pixels.iter().map(|pixel| pixel.value)),
+            ));
+        }
+        for ((title, data), rect) in rows.iter().zip(left_rects.iter()) {
+            let sparkline = w::Sparkline::default()
+                .block(
+                    w::Block::default()
+                        .title(title.as_str())
+                        .borders(w::Borders::ALL),
+                )
+                .data(data)
+                .max((max_value * scale) as u64);
+            frame.render_widget(sparkline, *rect);
+        }
+
+        let text = vec![
+            Spans(vec![
+                Span::styled("Frame: ", Style::default().fg(Color::DarkGray)),
+                Span::raw(format!("{}", self.frame_index)),
+            ]),
+            Spans(vec![
+                Span::styled("Candidates: ", Style::default().fg(Color::DarkGray)),
+                Span::raw(format!(
+                    "{} x {:?}",
+                    self.restir.initial_samples, self.restir.distribution
+                )),
+            ]),
+        ];
+        let text_block = w::Paragraph::new(text)
+            .block(w::Block::default().title("Info").borders(w::Borders::ALL))
+            .wrap(w::Wrap { trim: true });
+        frame.render_widget(text_block, hor_rects[1]);
+    }
+}
+
+fn main() {
+    use crossterm::event as ev;
+
+    let surface_length = 60;
+    let world = WorldConfig {
+        surface_length,
+        fog_height: 20.0,
+        fog_density: 0.1,
+        lamp_position: glam::vec2(30.0, 15.0),
+        lamp_intensity: 20.0,
+        occluder_y: 10.0,
+        occluder_x: 24.0..32.0,
+    };
+    let mut render = Render {
+        reference: Render::compute_reference(&world, 10000),
+        world,
+        restir: RestirConfig {
+            distribution: CandidateDistribution::Equiangular,
+            initial_samples: 1,
+            max_temporal_history: 20,
+            max_spatial_history: 10,
+        },
+        layers: [
+            Layer::new(surface_length, false),
+            Layer::new(surface_length, true),
+        ],
+        random: rand::thread_rng(),
+        frame_index: 0,
+    };
+
+    let mut output = Output::grab().unwrap();
+    loop {
+        render.update();
+        output.terminal.draw(|f| render.draw(f)).unwrap();
+
+        while ev::poll(Duration::from_millis(10)).unwrap() {
+            if let ev::Event::Key(event) = ev::read().unwrap() {
+                match event.code {
+                    ev::KeyCode::Esc => {
+                        return;
+                    }
+                    ev::KeyCode::Char('e') => {
+                        render.restir.distribution = match render.restir.distribution {
+                            CandidateDistribution::Distance => CandidateDistribution::Equiangular,
+                            CandidateDistribution::Equiangular => CandidateDistribution::Distance,
+                        };
+                    }
+                    ev::KeyCode::Char(',') if render.restir.initial_samples > 1 => {
+                        render.restir.initial_samples -= 1;
+                    }
+                    ev::KeyCode::Char('.') => {
+                        render.restir.initial_samples += 1;
+                    }
+                    _ => {}
+                }
+            }
+        }
+    }
+}
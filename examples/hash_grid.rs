@@ -0,0 +1,383 @@
+/*!
+World-space reservoir cache.
+
+The 2D world is a long ground line lit by the sun and the sky, with a few
+occluders casting shadows. The camera only sees a window of the ground,
+and cuts between a few viewpoints, occasionally returning to the previous ones.
+
+Screen-space temporal reuse reprojects the reservoirs of the previous frame,
+so the history of the ground that leaves the view is lost, and it has to
+converge from scratch when it comes back into view.
+The alternative stores the reservoirs in a hash grid keyed by the world
+position, which keeps them around while they are out of view. The grid has
+a fixed capacity, so it evicts the least recently used entries when full.
+The cached samples are re-validated once they get too old, so that
+they don't live forever in the regions that are always reused.
+
+The example prints the RMSE of the visible window for both approaches,
+averaged over the frames after each camera cut,
+together with the statistics of the cache.
+!*/
+
+use rand::SeedableRng as _;
+use std::{f32::consts::PI, ops::Range};
+
+struct World {
+    length: u16,
+    sun_position: glam::Vec2,
+    sun_radius: f32,
+    sun_color: glam::Vec3,
+    sky_color: glam::Vec3,
+    occluder_y: f32,
+    occluders: Vec<Range<f32>>,
+}
+
+impl World {
+    /// Incoming light from the given angle above the ground, including visibility.
+    fn evaluate(&self, origin: glam::Vec2, alpha: f32) -> glam::Vec3 {
+        let dir = glam::vec2(alpha.cos(), alpha.sin());
+        let x = origin.x + dir.x * (self.occluder_y - origin.y) / dir.y;
+        if self.occluders.iter().any(|range| range.contains(&x)) {
+            return glam::Vec3::ZERO;
+        }
+        let diff = self.sun_position - origin;
+        let leftover = diff - diff.dot(dir) * dir;
+        if leftover.length_squared() < self.sun_radius * self.sun_radius {
+            self.sun_color
+        } else {
+            self.sky_color
+        }
+    }
+
+    fn compute_reference(&self, direction_count: u32) -> Vec<glam::Vec3> {
+        let step = PI / direction_count as f32;
+        (0..self.length)
+            .map(|x| {
+                let origin = glam::vec2(x as f32 + 0.5, 0.0);
+                (0..direction_count)
+                    .map(|i| self.evaluate(origin, (i as f32 + 0.5) * step))
+                    .fold(glam::Vec3::ZERO, |sum, color| sum + color)
+                    * step
+            })
+            .collect()
+    }
+}
+
+#[derive(Clone, Copy, Default)]
+struct Sample {
+    alpha: f32,
+    color: glam::Vec3,
+}
+
+#[derive(Clone, Default)]
+struct Pixel {
+    reservoir: rs_voir::Reservoir<Sample>,
+}
+
+impl Pixel {
+    fn color(&self) -> glam::Vec3 {
+        self.reservoir.sample().color * self.reservoir.contribution_weight()
+    }
+}
+
+struct RestirConfig {
+    initial_samples: u32,
+    max_temporal_history: u32,
+}
+
+impl RestirConfig {
+    /// Resample the lighting of a ground point, reusing the previous reservoir if any.
+    fn resample(
+        &self,
+        world: &World,
+        origin: glam::Vec2,
+        prev: Option<&Pixel>,
+        random: &mut impl rand::Rng,
+    ) -> Pixel {
+        let mut builder = rs_voir::ReservoirBuilder::default();
+        for _ in 0..self.initial_samples {
+            let (dir, source_pdf) = rs_voir::sampling::uniform_semicircle(random);
+            let alpha = dir[1].atan2(dir[0]);
+            let color = world.evaluate(origin, alpha);
+            builder.stream(
+                source_pdf,
+                rs_voir::target::norm(color.to_array()),
+                Sample { alpha, color },
+                random,
+            );
+        }
+        if let Some(prev) = prev {
+            // the ground point is the same, so the target PDF is still valid
+            let other = prev
+                .reservoir
+                .with_max_history(self.max_temporal_history)
+                .to_builder_cached();
+            builder.merge(&other, random);
+        }
+        Pixel {
+            reservoir: builder.finish(),
+        }
+    }
+}
+
+/// Window of the world that is visible on the screen.
+#[derive(Clone, Copy)]
+struct Camera {
+    offset: u16,
+    width: u16,
+}
+
+impl Camera {
+    fn screen_to_world(&self, screen_x: u16) -> u16 {
+        self.offset + screen_x
+    }
+
+    fn world_to_screen(&self, world_x: u16) -> Option<u16> {
+        world_x
+            .checked_sub(self.offset)
+            .filter(|&screen_x| screen_x < self.width)
+    }
+}
+
+/// Temporal reuse with the reservoirs reprojected from the previous frame.
+struct ScreenSpace {
+    camera: Camera,
+    pixels: Vec<Pixel>,
+}
+
+impl ScreenSpace {
+    fn update(
+        &mut self,
+        camera: Camera,
+        world: &World,
+        restir: &RestirConfig,
+        random: &mut impl rand::Rng,
+    ) {
+        let prev_camera = self.camera;
+        let prev_pixels = std::mem::take(&mut self.pixels);
+        self.pixels = (0..camera.width)
+            .map(|screen_x| {
+                let world_x = camera.screen_to_world(screen_x);
+                let prev = prev_camera
+                    .world_to_screen(world_x)
+                    .and_then(|prev_x| prev_pixels.get(prev_x as usize));
+                let origin = glam::vec2(world_x as f32 + 0.5, 0.0);
+                restir.resample(world, origin, prev, random)
+            })
+            .collect();
+        self.camera = camera;
+    }
+}
+
+struct Entry {
+    key: i32,
+    pixel: Pixel,
+    last_used_frame: usize,
+}
+
+#[derive(Default)]
+struct CacheStats {
+    hits: usize,
+    misses: usize,
+    evictions: usize,
+    revalidations: usize,
+}
+
+/// Hash grid of reservoirs, keyed by the quantized world position.
+///
+/// Uses open addressing with a bounded number of probes,
+/// evicting the least recently used entry among the probed ones.
+struct HashGrid {
+    cell_size: f32,
+    entries: Vec<Option<Entry>>,
+    stats: CacheStats,
+}
+
+impl HashGrid {
+    const MAX_PROBES: usize = 8;
+
+    fn new(cell_size: f32, capacity: usize) -> Self {
+        Self {
+            cell_size,
+            entries: (0..capacity).map(|_| None).collect(),
+            stats: CacheStats::default(),
+        }
+    }
+
+    fn key(&self, position: glam::Vec2) -> i32 {
+        (position.x / self.cell_size).floor() as i32
+    }
+
+    /// Slots to probe for the given key.
+    fn probes(&self, key: i32) -> impl Iterator<Item = usize> {
+        // integer hash by Thomas Wang, to spread neighboring cells apart
+        let mut hash = key as u32;
+        hash = (hash ^ 61) ^ (hash >> 16);
+        hash = hash.wrapping_mul(9);
+        hash ^= hash >> 4;
+        hash = hash.wrapping_mul(0x27d4_eb2d);
+        hash ^= hash >> 15;
+        let capacity = self.entries.len();
+        (0..Self::MAX_PROBES).map(move |i| (hash as usize + i) % capacity)
+    }
+
+    fn lookup(&mut self, position: glam::Vec2) -> Option<&Pixel> {
+        let key = self.key(position);
+        let found = self.probes(key).find(|&slot| {
+            self.entries[slot]
+                .as_ref()
+                .is_some_and(|entry| entry.key == key)
+        });
+        match found {
+            Some(slot) => {
+                self.stats.hits += 1;
+                self.entries[slot].as_ref().map(|entry| &entry.pixel)
+            }
+            None => {
+                self.stats.misses += 1;
+                None
+            }
+        }
+    }
+
+    fn insert(&mut self, position: glam::Vec2, pixel: Pixel, frame_index: usize) {
+        let key = self.key(position);
+        let slot = self
+            .probes(key)
+            .min_by_key(|&slot| match self.entries[slot] {
+                // prefer the slot with the same key, then an empty one
+                Some(ref entry) if entry.key == key => (0, 0),
+                None => (1, 0),
+                Some(ref entry) => (2, entry.last_used_frame),
+            })
+            .unwrap();
+        if matches!(self.entries[slot], Some(ref entry) if entry.key != key) {
+            self.stats.evictions += 1;
+        }
+        self.entries[slot] = Some(Entry {
+            key,
+            pixel,
+            last_used_frame: frame_index,
+        });
+    }
+}
+
+/// Temporal reuse with the reservoirs stored in the world space.
+struct WorldSpace {
+    grid: HashGrid,
+    max_sample_age: u32,
+    pixels: Vec<Pixel>,
+}
+
+impl WorldSpace {
+    fn update(
+        &mut self,
+        camera: Camera,
+        frame_index: usize,
+        world: &World,
+        restir: &RestirConfig,
+        random: &mut impl rand::Rng,
+    ) {
+        self.pixels.clear();
+        for screen_x in 0..camera.width {
+            let world_x = camera.screen_to_world(screen_x);
+            let origin = glam::vec2(world_x as f32 + 0.5, 0.0);
+            let prev = self.grid.lookup(origin).cloned().map(|mut prev| {
+                if prev.reservoir.is_expired(self.max_sample_age) {
+                    // the lighting is static here, but in general it could have changed
+                    let old_color = prev.reservoir.sample().color;
+                    let color = world.evaluate(origin, prev.reservoir.sample().alpha);
+                    prev.reservoir = prev
+                        .reservoir
+                        .mutate(
+                            rs_voir::target::norm(old_color.to_array()),
+                            rs_voir::target::norm(color.to_array()),
+                        )
+                        .revalidated();
+                    prev.reservoir.sample_mut().color = color;
+                    self.grid.stats.revalidations += 1;
+                }
+                prev
+            });
+            let pixel = restir.resample(world, origin, prev.as_ref(), random);
+            let cached = Pixel {
+                reservoir: pixel.reservoir.aged(),
+            };
+            self.grid.insert(origin, cached, frame_index);
+            self.pixels.push(pixel);
+        }
+    }
+}
+
+fn compute_rmse(pixels: &[Pixel], camera: Camera, reference: &[glam::Vec3]) -> f32 {
+    let sum = pixels
+        .iter()
+        .enumerate()
+        .map(|(screen_x, pixel)| {
+            let world_x = camera.screen_to_world(screen_x as u16);
+            (pixel.color() - reference[world_x as usize]).length_squared()
+        })
+        .sum::<f32>();
+    (sum / pixels.len() as f32).sqrt()
+}
+
+fn main() {
+    let mut random = rand::rngs::StdRng::seed_from_u64(0);
+    let world = World {
+        length: 200,
+        sun_position: glam::vec2(100.0, 40.0),
+        sun_radius: 2.0,
+        sun_color: glam::vec3(10.0, 10.0, 1.0),
+        sky_color: glam::vec3(0.0, 0.0, 0.1),
+        occluder_y: 5.0,
+        occluders: vec![20.0..35.0, 70.0..80.0, 120.0..150.0, 170.0..175.0],
+    };
+    let reference = world.compute_reference(4096);
+    let restir = RestirConfig {
+        initial_samples: 1,
+        max_temporal_history: 20,
+    };
+
+    let camera_width = 40;
+    let viewpoints = [0, 60, 0, 120, 160, 120, 60];
+    let frames_per_viewpoint = 10;
+    let mut screen_space = ScreenSpace {
+        camera: Camera {
+            offset: 0,
+            width: 0,
+        },
+        pixels: Vec::new(),
+    };
+    let mut world_space = WorldSpace {
+        grid: HashGrid::new(1.0, 160),
+        max_sample_age: 30,
+        pixels: Vec::new(),
+    };
+
+    println!("camera	screen RMSE	world RMSE	cache hits	misses	evictions	revalidations");
+    for (cut_index, &offset) in viewpoints.iter().cycle().take(20).enumerate() {
+        let camera = Camera {
+            offset,
+            width: camera_width,
+        };
+        let mut rmse = [0.0; 2];
+        for i in 0..frames_per_viewpoint {
+            let frame_index = cut_index * frames_per_viewpoint + i;
+            screen_space.update(camera, &world, &restir, &mut random);
+            world_space.update(camera, frame_index, &world, &restir, &mut random);
+            rmse[0] += compute_rmse(&screen_space.pixels, camera, &reference);
+            rmse[1] += compute_rmse(&world_space.pixels, camera, &reference);
+        }
+        let stats = std::mem::take(&mut world_space.grid.stats);
+        println!(
+            "{}\t{:.4}\t\t{:.4}\t\t{}\t\t{}\t{}\t\t{}",
+            offset,
+            rmse[0] / frames_per_viewpoint as f32,
+            rmse[1] / frames_per_viewpoint as f32,
+            stats.hits,
+            stats.misses,
+            stats.evictions,
+            stats.revalidations,
+        );
+    }
+}
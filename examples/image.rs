@@ -0,0 +1,423 @@
+/*!
+Offline 2D image rendering with reservoir resampling.
+
+The 2D world consists of:
+    - ground (the bottom edge of the image)
+    - occluder (a line segment at specified height)
+    - sun (a disk at specified location)
+    - sky (the rest of the upper hemisphere)
+
+Unlike the `restir` example, every point of the image is a receiver,
+gathering light from the full circle of directions around it.
+Each frame does RIS on the initial candidates followed by temporal reuse,
+then spatial reuse from a few random neighbors along both axes,
+and writes the result as a PNG file. Neighbors on the other side
+of the occluder line are rejected as dissimilar, the same way
+screen-space ReSTIR rejects neighbors across depth and normal discontinuities,
+and the rest get their history scaled by the similarity of the distance
+to the occluder line. A converged reference is computed by a dense quadrature over all directions and written next to the frames,
+together with the RMSE of each frame against it.
+
+Usage: `cargo run --example image -- [output_dir] [frame_count] [spatial_samples]`
+!*/
+
+use std::{f32::consts::PI, fs, io, ops::Range, path::Path};
+
+struct WorldConfig {
+    size: [u16; 2],
+    sun_position: [f32; 2],
+    sun_radius: f32,
+    sun_color: [f32; 3],
+    sky_color: [f32; 3],
+    occluder_y: f32,
+    occluder_x: Range<f32>,
+}
+
+#[derive(Clone, Default)]
+struct LightInfo {
+    color: glam::Vec3,
+    distance: Option<f32>,
+}
+
+impl LightInfo {
+    /// Returns the value we assign to this light, based on the color (or intensity).
+    /// This is also known as "unnormalized target PDF" in literature.
+    fn target_value(&self) -> f32 {
+        rs_voir::target::norm(self.color.to_array())
+    }
+}
+
+impl WorldConfig {
+    fn get_incoming_light(&self, origin: glam::Vec2, dir: glam::Vec2) -> LightInfo {
+        debug_assert!(dir.is_normalized());
+        let diff = glam::Vec2::from(self.sun_position) - origin;
+        let sun_distance = diff.dot(dir);
+        let leftover = diff - sun_distance * dir;
+        if sun_distance > 0.0 && leftover.length_squared() < self.sun_radius * self.sun_radius {
+            LightInfo {
+                color: self.sun_color.into(),
+                distance: Some(sun_distance),
+            }
+        } else if dir.y > 0.0 {
+            LightInfo {
+                color: self.sky_color.into(),
+                distance: None,
+            }
+        } else {
+            // hitting the ground
+            LightInfo::default()
+        }
+    }
+
+    fn check_visibility(&self, origin: glam::Vec2, dir: glam::Vec2, max_distance: f32) -> bool {
+        let dy = self.occluder_y - origin.y;
+        if dy * dir.y <= 0.0 {
+            // moving away from the occluder line
+            return true;
+        }
+        let t = dy / dir.y;
+        let x = origin.x + dir.x * t;
+        t > max_distance || !self.occluder_x.contains(&x)
+    }
+
+    fn evaluate(&self, origin: glam::Vec2, dir: glam::Vec2) -> LightInfo {
+        let light = self.get_incoming_light(origin, dir);
+        if self.check_visibility(origin, dir, light.distance.unwrap_or(f32::INFINITY)) {
+            light
+        } else {
+            LightInfo::default()
+        }
+    }
+
+    fn pixel_position(&self, index: usize) -> glam::Vec2 {
+        let width = self.size[0] as usize;
+        let x = index % width;
+        let y = self.size[1] as usize - 1 - index / width;
+        glam::vec2(x as f32 + 0.5, y as f32 + 0.5)
+    }
+
+    fn pixel_index(&self, position: glam::Vec2) -> Option<usize> {
+        let x = position.x.floor();
+        let y = self.size[1] as f32 - 1.0 - position.y.floor();
+        if x < 0.0 || y < 0.0 || x >= self.size[0] as f32 || y >= self.size[1] as f32 {
+            return None;
+        }
+        Some(y as usize * self.size[0] as usize + x as usize)
+    }
+
+    /// Compute how similar two receivers are, for sharing their samples.
+    ///
+    /// Receivers on the opposite sides of the occluder are unrelated,
+    /// otherwise they are compared by the distance to the occluder line.
+    fn similarity(&self, a: glam::Vec2, b: glam::Vec2) -> f32 {
+        let depths = [a.y - self.occluder_y, b.y - self.occluder_y];
+        if depths[0] * depths[1] > 0.0 {
+            let [near, far] = [depths[0].abs(), depths[1].abs()];
+            near.min(far) / near.max(far)
+        } else {
+            0.0
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct Sample {
+    dir: glam::Vec2,
+    light: LightInfo,
+}
+
+impl Sample {
+    /// Shift the sample into another receiver.
+    ///
+    /// Note: the Jacobian of the shift is ignored, like in the `restir` example.
+    fn shift_map(&self, src_origin: glam::Vec2, dst_origin: glam::Vec2) -> Self {
+        match self.light.distance {
+            Some(distance) => {
+                let diff = src_origin + distance * self.dir - dst_origin;
+                Self {
+                    dir: diff.normalize(),
+                    light: LightInfo {
+                        color: self.light.color,
+                        distance: Some(diff.length()),
+                    },
+                }
+            }
+            None => self.clone(),
+        }
+    }
+}
+
+#[derive(Clone, Default)]
+struct Pixel {
+    reservoir: rs_voir::Reservoir<Sample>,
+    color: glam::Vec3,
+}
+
+struct RestirConfig {
+    initial_samples: u32,
+    max_temporal_history: u32,
+    spatial_samples: u32,
+    /// Radius of the disk around the pixel that spatial neighbors are picked from.
+    spatial_radius: f32,
+    max_spatial_history: u32,
+}
+
+struct Render {
+    world: WorldConfig,
+    restir: RestirConfig,
+    pixels: Box<[Pixel]>,
+    random: rand::rngs::ThreadRng,
+}
+
+impl Render {
+    fn update(&mut self) {
+        use rand::Rng;
+
+        for (index, pixel) in self.pixels.iter_mut().enumerate() {
+            let position = self.world.pixel_position(index);
+            let mut builder = rs_voir::ReservoirBuilder::default();
+
+            // First, do RIS on the initial samples
+            for _ in 0..self.restir.initial_samples {
+                let (dir, source_pdf) = rs_voir::sampling::uniform_circle(&mut self.random);
+                let dir = glam::Vec2::from(dir);
+                let light = self.world.evaluate(position, dir);
+                let target_value = light.target_value();
+                let sample = Sample { dir, light };
+                builder.stream(source_pdf, target_value, sample, &mut self.random);
+            }
+
+            // Second, reuse the previous frame reservoir.
+            if self.restir.max_temporal_history != 0 {
+                let prev = pixel
+                    .reservoir
+                    .with_max_history(self.restir.max_temporal_history);
+                if prev.has_weight() {
+                    let other = prev.to_builder(prev.sample().light.target_value());
+                    builder.merge(&other, &mut self.random);
+                } else {
+                    builder.merge_history(&prev);
+                }
+            }
+
+            pixel.reservoir = builder.finish();
+        }
+
+        // Third, reuse the reservoirs of the spatial neighbors in both dimensions.
+        if self.restir.spatial_samples != 0 {
+            let temporal = self.pixels.clone();
+            for (index, pixel) in self.pixels.iter_mut().enumerate() {
+                let position = self.world.pixel_position(index);
+                let current = &temporal[index].reservoir;
+                let mut builder = current.to_builder(current.sample().light.target_value());
+                for _ in 0..self.restir.spatial_samples {
+                    let alpha = self.random.gen_range(0.0..2.0 * PI);
+                    let radius = self.restir.spatial_radius * self.random.gen::<f32>().sqrt();
+                    let other_position = position + radius * glam::vec2(alpha.cos(), alpha.sin());
+                    let other_index = match self.world.pixel_index(other_position) {
+                        Some(other_index) if other_index != index => other_index,
+                        _ => continue,
+                    };
+                    let other = &temporal[other_index];
+                    let other_position = self.world.pixel_position(other_index);
+                    let similarity = self.world.similarity(position, other_position);
+                    if similarity <= 0.0 {
+                        continue;
+                    }
+
+                    let prev = other
+                        .reservoir
+                        .with_max_history(self.restir.max_spatial_history)
+                        .with_similarity(similarity);
+                    let sample = prev.sample();
+                    let shifted = sample.shift_map(other_position, position);
+                    let distance = shifted.light.distance.unwrap_or(f32::INFINITY);
+                    if prev.has_weight()
+                        && self.world.check_visibility(position, shifted.dir, distance)
+                    {
+                        let other_builder = prev
+                            .with_sample(shifted)
+                            .to_builder(sample.light.target_value());
+                        builder.merge(&other_builder, &mut self.random);
+                    } else {
+                        builder.merge_history(&prev);
+                    }
+                }
+                pixel.reservoir = builder.finish();
+            }
+        }
+
+        for pixel in self.pixels.iter_mut() {
+            pixel.color =
+                pixel.reservoir.sample().light.color * pixel.reservoir.contribution_weight();
+        }
+    }
+
+    fn compute_reference(&self, direction_count: u32) -> Vec<glam::Vec3> {
+        let step = 2.0 * PI / direction_count as f32;
+        (0..self.pixels.len())
+            .map(|index| {
+                let position = self.world.pixel_position(index);
+                (0..direction_count)
+                    .map(|i| {
+                        let alpha = (i as f32 + 0.5) * step;
+                        let dir = glam::vec2(alpha.cos(), alpha.sin());
+                        self.world.evaluate(position, dir).color
+                    })
+                    .fold(glam::Vec3::ZERO, |sum, color| sum + color)
+                    * step
+            })
+            .collect()
+    }
+}
+
+fn compute_rmse(colors: impl Iterator<Item = glam::Vec3>, reference: &[glam::Vec3]) -> f32 {
+    let sum = colors
+        .zip(reference)
+        .map(|(color, &reference)| (color - reference).length_squared())
+        .sum::<f32>();
+    (sum / reference.len() as f32).sqrt()
+}
+
+/// Minimal PNG encoder for 8-bit RGB images.
+///
+/// Uses uncompressed ("stored") deflate blocks, trading file size
+/// for not having to depend on an image crate.
+mod png {
+    use std::io::{self, Write};
+
+    fn crc32(chunks: &[&[u8]]) -> u32 {
+        let mut crc = !0u32;
+        for &byte in chunks.iter().flat_map(|chunk| chunk.iter()) {
+            crc ^= byte as u32;
+            for _ in 0..8 {
+                let mask = (crc & 1).wrapping_neg();
+                crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+            }
+        }
+        !crc
+    }
+
+    fn adler32(data: &[u8]) -> u32 {
+        let (mut a, mut b) = (1u32, 0u32);
+        for &byte in data {
+            a = (a + byte as u32) % 65521;
+            b = (b + a) % 65521;
+        }
+        (b << 16) | a
+    }
+
+    fn write_chunk(out: &mut impl Write, kind: &[u8; 4], data: &[u8]) -> io::Result<()> {
+        out.write_all(&(data.len() as u32).to_be_bytes())?;
+        out.write_all(kind)?;
+        out.write_all(data)?;
+        out.write_all(&crc32(&[kind, data]).to_be_bytes())
+    }
+
+    pub fn write_rgb(out: &mut impl Write, size: [u32; 2], rgb: &[u8]) -> io::Result<()> {
+        assert_eq!(rgb.len(), size[0] as usize * size[1] as usize * 3);
+        out.write_all(b"\x89PNG\r\n\x1a\n")?;
+
+        let mut header = Vec::with_capacity(13);
+        header.extend_from_slice(&size[0].to_be_bytes());
+        header.extend_from_slice(&size[1].to_be_bytes());
+        // bit depth, color type (RGB), compression, filter, interlace
+        header.extend_from_slice(&[8, 2, 0, 0, 0]);
+        write_chunk(out, b"IHDR", &header)?;
+
+        // every scanline is prefixed with the filter type
+        let raw = rgb
+            .chunks(size[0] as usize * 3)
+            .flat_map(|row| std::iter::once(0).chain(row.iter().cloned()))
+            .collect::<Vec<u8>>();
+        let mut zlib = vec![0x78, 0x01];
+        let mut blocks = raw.chunks(0xFFFF).peekable();
+        while let Some(block) = blocks.next() {
+            let is_final = blocks.peek().is_none();
+            let len = block.len() as u16;
+            zlib.push(is_final as u8);
+            zlib.extend_from_slice(&len.to_le_bytes());
+            zlib.extend_from_slice(&(!len).to_le_bytes());
+            zlib.extend_from_slice(block);
+        }
+        zlib.extend_from_slice(&adler32(&raw).to_be_bytes());
+        write_chunk(out, b"IDAT", &zlib)?;
+
+        write_chunk(out, b"IEND", &[])
+    }
+}
+
+fn save_image(
+    path: &Path,
+    size: [u16; 2],
+    colors: impl Iterator<Item = glam::Vec3>,
+) -> io::Result<()> {
+    let exposure = 1.0;
+    let rgb = colors
+        .flat_map(|color| {
+            let mapped = (color * exposure).clamp(glam::Vec3::ZERO, glam::Vec3::ONE);
+            mapped
+                .to_array()
+                .map(|c| (c.powf(1.0 / 2.2) * 255.0 + 0.5) as u8)
+        })
+        .collect::<Vec<u8>>();
+    let mut file = io::BufWriter::new(fs::File::create(path)?);
+    png::write_rgb(&mut file, [size[0] as u32, size[1] as u32], &rgb)
+}
+
+fn main() -> io::Result<()> {
+    let mut args = std::env::args().skip(1);
+    let output_dir = args.next().unwrap_or_else(|| "target/image".to_string());
+    let frame_count = args
+        .next()
+        .map_or(16, |arg| arg.parse::<usize>().expect("frame count"));
+    let spatial_samples = args
+        .next()
+        .map_or(3, |arg| arg.parse::<u32>().expect("spatial sample count"));
+    let output_dir = Path::new(&output_dir);
+    fs::create_dir_all(output_dir)?;
+
+    let size = [160, 90];
+    let mut render = Render {
+        world: WorldConfig {
+            size,
+            sun_position: [40.0, 70.0],
+            sun_radius: 4.0,
+            sun_color: [4.0, 4.0, 0.4],
+            sky_color: [0.05, 0.05, 0.2],
+            occluder_y: 40.0,
+            occluder_x: 50.0..90.0,
+        },
+        restir: RestirConfig {
+            initial_samples: 4,
+            max_temporal_history: 20,
+            spatial_samples,
+            spatial_radius: 4.0,
+            max_spatial_history: 20,
+        },
+        pixels: (0..size[0] as usize * size[1] as usize)
+            .map(|_| Pixel::default())
+            .collect(),
+        random: rand::thread_rng(),
+    };
+
+    let reference = render.compute_reference(4096);
+    save_image(
+        &output_dir.join("reference.png"),
+        size,
+        reference.iter().cloned(),
+    )?;
+
+    for frame_index in 0..frame_count {
+        render.update();
+        let colors = || render.pixels.iter().map(|pixel| pixel.color);
+        let path = output_dir.join(format!("frame-{:03}.png", frame_index));
+        save_image(&path, size, colors())?;
+        println!(
+            "{}: RMSE = {:.4}",
+            path.display(),
+            compute_rmse(colors(), &reference)
+        );
+    }
+    Ok(())
+}
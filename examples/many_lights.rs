@@ -0,0 +1,217 @@
+/*!
+Many-light resampling example.
+
+The 2D world consists of a ground line lit by dozens of point lights
+of varying intensity, with an occluder casting shadows in between.
+
+Initial candidates are picked either uniformly from the light list,
+or proportionally to the light power using an alias table.
+RIS then resamples them according to the unshadowed contribution
+of each light, and only the selected light is tested for visibility.
+
+The example prints the RMSE against the exact solution for different
+numbers of candidates, showing how RIS quality depends on the
+candidate distribution.
+!*/
+
+use rand::SeedableRng as _;
+use std::ops::Range;
+
+struct PointLight {
+    position: glam::Vec2,
+    intensity: f32,
+}
+
+struct World {
+    surface_length: u16,
+    lights: Vec<PointLight>,
+    occluder_y: f32,
+    occluder_x: Range<f32>,
+}
+
+impl World {
+    fn generate(surface_length: u16, light_count: usize, random: &mut impl rand::Rng) -> Self {
+        let lights = (0..light_count)
+            .map(|_| PointLight {
+                position: glam::vec2(
+                    random.gen_range(0.0..surface_length as f32),
+                    random.gen_range(5.0..20.0),
+                ),
+                // a few bright lights and many dim ones
+                intensity: random.gen::<f32>().powi(4) * 10.0,
+            })
+            .collect();
+        Self {
+            surface_length,
+            lights,
+            occluder_y: 3.0,
+            occluder_x: 15.0..25.0,
+        }
+    }
+
+    /// Unshadowed contribution of a light to a point on the ground.
+    fn unshadowed_contribution(&self, light: &PointLight, origin: glam::Vec2) -> f32 {
+        let diff = light.position - origin;
+        let distance = diff.length();
+        // cosine against the ground normal, with 1/distance falloff in 2D
+        light.intensity * diff.y / (distance * distance)
+    }
+
+    fn check_visibility(&self, origin: glam::Vec2, target: glam::Vec2) -> bool {
+        let t = (self.occluder_y - origin.y) / (target.y - origin.y);
+        if !(0.0..1.0).contains(&t) {
+            return true;
+        }
+        let x = origin.x + (target.x - origin.x) * t;
+        !self.occluder_x.contains(&x)
+    }
+
+    fn contribution(&self, light: &PointLight, origin: glam::Vec2) -> f32 {
+        if self.check_visibility(origin, light.position) {
+            self.unshadowed_contribution(light, origin)
+        } else {
+            0.0
+        }
+    }
+}
+
+/// Walker's alias table for sampling discrete distributions in constant time.
+struct AliasTable {
+    entries: Vec<AliasEntry>,
+    pdfs: Vec<f32>,
+}
+
+struct AliasEntry {
+    threshold: f32,
+    alias: usize,
+}
+
+impl AliasTable {
+    fn new(weights: &[f32]) -> Self {
+        let count = weights.len();
+        let total = weights.iter().sum::<f32>();
+        let pdfs = weights.iter().map(|w| w / total).collect::<Vec<_>>();
+        let mut scaled = pdfs.iter().map(|p| p * count as f32).collect::<Vec<_>>();
+        let mut entries = (0..count)
+            .map(|i| AliasEntry {
+                threshold: 1.0,
+                alias: i,
+            })
+            .collect::<Vec<_>>();
+
+        let (mut small, mut large): (Vec<usize>, Vec<usize>) =
+            (0..count).partition(|&i| scaled[i] < 1.0);
+        while let (Some(s), Some(&l)) = (small.pop(), large.last()) {
+            entries[s] = AliasEntry {
+                threshold: scaled[s],
+                alias: l,
+            };
+            scaled[l] -= 1.0 - scaled[s];
+            if scaled[l] < 1.0 {
+                large.pop();
+                small.push(l);
+            }
+        }
+        Self { entries, pdfs }
+    }
+
+    /// Sample an index, returning it together with its probability.
+    fn sample(&self, random: &mut impl rand::Rng) -> (usize, f32) {
+        let index = random.gen_range(0..self.entries.len());
+        let entry = &self.entries[index];
+        let selected = if random.gen::<f32>() < entry.threshold {
+            index
+        } else {
+            entry.alias
+        };
+        (selected, self.pdfs[selected])
+    }
+}
+
+#[derive(Clone, Copy, Debug)]
+enum CandidateDistribution {
+    Uniform,
+    Power,
+}
+
+struct Estimator<'a> {
+    world: &'a World,
+    alias_table: AliasTable,
+}
+
+impl Estimator<'_> {
+    fn estimate(
+        &self,
+        origin: glam::Vec2,
+        candidate_count: u32,
+        distribution: CandidateDistribution,
+        random: &mut impl rand::Rng,
+    ) -> f32 {
+        let mut builder = rs_voir::ReservoirBuilder::default();
+        for _ in 0..candidate_count {
+            let (index, source_pdf) = match distribution {
+                CandidateDistribution::Uniform => {
+                    let count = self.world.lights.len();
+                    (random.gen_range(0..count), 1.0 / count as f32)
+                }
+                CandidateDistribution::Power => self.alias_table.sample(random),
+            };
+            let light = &self.world.lights[index];
+            let target_value = self.world.unshadowed_contribution(light, origin);
+            builder.stream(source_pdf, target_value, Some(light), random);
+        }
+
+        let reservoir = builder.finish();
+        match *reservoir.sample() {
+            Some(light) => self.world.contribution(light, origin) * reservoir.contribution_weight(),
+            None => 0.0,
+        }
+    }
+}
+
+fn main() {
+    let mut random = rand::rngs::StdRng::seed_from_u64(0);
+    let world = World::generate(40, 50, &mut random);
+    let estimator = Estimator {
+        world: &world,
+        alias_table: AliasTable::new(
+            &world
+                .lights
+                .iter()
+                .map(|light| light.intensity)
+                .collect::<Vec<_>>(),
+        ),
+    };
+
+    let receivers = (0..world.surface_length)
+        .map(|x| glam::vec2(x as f32 + 0.5, 0.0))
+        .collect::<Vec<_>>();
+    let reference = receivers
+        .iter()
+        .map(|&origin| {
+            world
+                .lights
+                .iter()
+                .map(|light| world.contribution(light, origin))
+                .sum::<f32>()
+        })
+        .collect::<Vec<_>>();
+
+    let trial_count = 1000;
+    println!("candidates\tuniform RMSE\tpower RMSE");
+    for candidate_count in [1, 2, 4, 8, 16, 32] {
+        let rmse =
+            [CandidateDistribution::Uniform, CandidateDistribution::Power].map(|distribution| {
+                let mut sum = 0.0;
+                for _ in 0..trial_count {
+                    for (&origin, &expected) in receivers.iter().zip(&reference) {
+                        let value =
+                            estimator.estimate(origin, candidate_count, distribution, &mut random);
+                        sum += (value - expected) * (value - expected);
+                    }
+                }
+                (sum / (trial_count * receivers.len()) as f32).sqrt()
+            });
+        println!("{}\t\t{:.4}\t\t{:.4}", candidate_count, rmse[0], rmse[1]);
+    }
+}
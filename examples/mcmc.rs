@@ -0,0 +1,208 @@
+/*!
+Hybrid of resampling and Markov Chain Monte Carlo.
+
+The 2D world consists of a ground line lit by the sun and the sky,
+with an occluder casting a shadow in between, same as in the `restir` example.
+
+Every frame does RIS on the initial candidates followed by temporal reuse.
+Temporal reuse keeps the good samples around for many frames, which makes
+the consecutive frames correlated, and the accumulated image converges slowly.
+The hybrid variant additionally mutates the selected sample of every reservoir
+with a few Metropolis steps, accepted by the ratio of the target PDFs.
+The mutations preserve the distribution of the samples, and the contribution
+weight is adjusted with `Reservoir::mutate`.
+
+The example prints the RMSE against the exact solution, both for
+a single frame and for the average of all the frames so far.
+!*/
+
+use rand::SeedableRng as _;
+use std::{f32::consts::PI, ops::Range};
+
+struct World {
+    surface_length: u16,
+    sun_position: glam::Vec2,
+    sun_radius: f32,
+    sun_color: glam::Vec3,
+    sky_color: glam::Vec3,
+    occluder_y: f32,
+    occluder_x: Range<f32>,
+}
+
+impl World {
+    /// Incoming light from the given angle above the ground, including visibility.
+    fn evaluate(&self, origin: glam::Vec2, alpha: f32) -> glam::Vec3 {
+        if !(0.0..PI).contains(&alpha) {
+            return glam::Vec3::ZERO;
+        }
+        let dir = glam::vec2(alpha.cos(), alpha.sin());
+        let t = (self.occluder_y - origin.y) / dir.y;
+        if self.occluder_x.contains(&(origin.x + dir.x * t)) {
+            return glam::Vec3::ZERO;
+        }
+        let diff = self.sun_position - origin;
+        let leftover = diff - diff.dot(dir) * dir;
+        if leftover.length_squared() < self.sun_radius * self.sun_radius {
+            self.sun_color
+        } else {
+            self.sky_color
+        }
+    }
+
+    fn receiver(&self, index: usize) -> glam::Vec2 {
+        glam::vec2(index as f32 + 0.5, 0.0)
+    }
+
+    fn compute_reference(&self, direction_count: u32) -> Vec<glam::Vec3> {
+        let step = PI / direction_count as f32;
+        (0..self.surface_length as usize)
+            .map(|index| {
+                (0..direction_count)
+                    .map(|i| self.evaluate(self.receiver(index), (i as f32 + 0.5) * step))
+                    .fold(glam::Vec3::ZERO, |sum, color| sum + color)
+                    * step
+            })
+            .collect()
+    }
+}
+
+fn target_value(color: glam::Vec3) -> f32 {
+    rs_voir::target::norm(color.to_array())
+}
+
+#[derive(Clone, Copy, Default)]
+struct Sample {
+    alpha: f32,
+    color: glam::Vec3,
+}
+
+struct MutationConfig {
+    steps: u32,
+    /// Maximum angle of a single random walk step.
+    max_angle: f32,
+}
+
+struct Render<'a> {
+    world: &'a World,
+    initial_samples: u32,
+    max_temporal_history: u32,
+    mutation: Option<MutationConfig>,
+    pixels: Vec<rs_voir::Reservoir<Sample>>,
+}
+
+impl Render<'_> {
+    fn update(&mut self, random: &mut impl rand::Rng) {
+        for (index, pixel) in self.pixels.iter_mut().enumerate() {
+            let origin = self.world.receiver(index);
+            let mut builder = rs_voir::ReservoirBuilder::default();
+
+            for _ in 0..self.initial_samples {
+                let (dir, source_pdf) = rs_voir::sampling::uniform_semicircle(random);
+                let alpha = dir[1].atan2(dir[0]);
+                let color = self.world.evaluate(origin, alpha);
+                builder.stream(
+                    source_pdf,
+                    target_value(color),
+                    Sample { alpha, color },
+                    random,
+                );
+            }
+
+            builder.merge_reservoir(
+                pixel,
+                target_value(pixel.sample().color),
+                self.max_temporal_history,
+                random,
+            );
+            *pixel = builder.finish();
+
+            if let Some(ref mutation) = self.mutation {
+                if !pixel.has_weight() {
+                    continue;
+                }
+                let old_target = target_value(pixel.sample().color);
+                let mut sample = *pixel.sample();
+                for _ in 0..mutation.steps {
+                    // symmetric random walk, so the acceptance is just the target ratio
+                    let alpha =
+                        sample.alpha + random.gen_range(-mutation.max_angle..mutation.max_angle);
+                    let color = self.world.evaluate(origin, alpha);
+                    let acceptance = target_value(color) / target_value(sample.color);
+                    if random.gen::<f32>() < acceptance {
+                        sample = Sample { alpha, color };
+                    }
+                }
+                *pixel = pixel.mutate(old_target, target_value(sample.color));
+                *pixel.sample_mut() = sample;
+            }
+        }
+    }
+
+    fn colors(&self) -> impl Iterator<Item = glam::Vec3> + '_ {
+        self.pixels
+            .iter()
+            .map(|pixel| pixel.sample().color * pixel.contribution_weight())
+    }
+}
+
+fn compute_rmse(colors: impl Iterator<Item = glam::Vec3>, reference: &[glam::Vec3]) -> f32 {
+    let sum = colors
+        .zip(reference)
+        .map(|(color, &reference)| (color - reference).length_squared())
+        .sum::<f32>();
+    (sum / reference.len() as f32).sqrt()
+}
+
+fn main() {
+    let mut random = rand::rngs::StdRng::seed_from_u64(0);
+    let world = World {
+        surface_length: 64,
+        sun_position: glam::vec2(10.0, 20.0),
+        sun_radius: 1.0,
+        sun_color: glam::vec3(10.0, 10.0, 1.0),
+        sky_color: glam::vec3(0.0, 0.0, 0.1),
+        occluder_y: 5.0,
+        occluder_x: 15.0..30.0,
+    };
+    let reference = world.compute_reference(4096);
+
+    let mut renders = [
+        None,
+        Some(MutationConfig {
+            steps: 4,
+            max_angle: 0.05,
+        }),
+    ]
+    .map(|mutation| Render {
+        world: &world,
+        initial_samples: 1,
+        max_temporal_history: 20,
+        mutation,
+        pixels: vec![rs_voir::Reservoir::default(); world.surface_length as usize],
+    });
+    let mut accumulated =
+        [(); 2].map(|_| vec![rs_voir::Accumulator::<3>::default(); world.surface_length as usize]);
+
+    let frame_count = 256u32;
+    println!("frame\tReSTIR RMSE (frame, average)\tReSTIR+MCMC RMSE (frame, average)");
+    for frame_index in 1..=frame_count {
+        let mut line = format!("{}", frame_index);
+        for (render, accumulators) in renders.iter_mut().zip(accumulated.iter_mut()) {
+            render.update(&mut random);
+            for (accumulator, color) in accumulators.iter_mut().zip(render.colors()) {
+                accumulator.add(color.to_array());
+            }
+            let average = accumulators
+                .iter()
+                .map(|accumulator| glam::Vec3::from(accumulator.mean()));
+            line += &format!(
+                "\t{:.4}, {:.4}\t\t",
+                compute_rmse(render.colors(), &reference),
+                compute_rmse(average, &reference)
+            );
+        }
+        if frame_index.is_power_of_two() {
+            println!("{}", line.trim_end());
+        }
+    }
+}
@@ -0,0 +1,151 @@
+/*!
+Prioritized experience replay with reservoirs.
+
+Simulates the replay buffer of a reinforcement learning agent:
+transitions arrive with the maximum priority seen so far,
+minibatches are sampled proportionally to the priorities,
+and the priorities of the sampled transitions get updated
+as the agent "learns" from them.
+
+Instead of maintaining a sum-tree over the whole buffer, every
+minibatch slot is filled by resampling a few uniformly chosen
+candidates with a reservoir. The contribution weight of the reservoir
+is an unbiased estimate of the inverse selection probability,
+which directly provides the importance weight correcting
+for the non-uniform sampling.
+
+The example periodically reports how the importance-weighted loss
+of a minibatch compares to the true mean loss over the buffer.
+!*/
+
+use rand::{Rng as _, SeedableRng as _};
+
+struct Transition {
+    priority: f32,
+    /// Loss of the agent on this transition, which the priority tracks.
+    loss: f32,
+}
+
+struct ReplayBuffer {
+    transitions: Vec<Transition>,
+    capacity: usize,
+    next_index: usize,
+    max_priority: f32,
+}
+
+/// Transition selected into a minibatch.
+struct Selection {
+    index: usize,
+    /// Importance weight, relative to uniform sampling of the buffer.
+    weight: f32,
+}
+
+impl ReplayBuffer {
+    fn new(capacity: usize) -> Self {
+        Self {
+            transitions: Vec::with_capacity(capacity),
+            capacity,
+            next_index: 0,
+            max_priority: 1.0,
+        }
+    }
+
+    fn push(&mut self, loss: f32) {
+        let transition = Transition {
+            priority: self.max_priority,
+            loss,
+        };
+        if self.transitions.len() < self.capacity {
+            self.transitions.push(transition);
+        } else {
+            self.transitions[self.next_index] = transition;
+        }
+        self.next_index = (self.next_index + 1) % self.capacity;
+    }
+
+    fn update_priority(&mut self, index: usize, priority: f32) {
+        self.transitions[index].priority = priority;
+        self.max_priority = self.max_priority.max(priority);
+    }
+
+    fn sample(
+        &self,
+        batch_size: usize,
+        candidate_count: u32,
+        random: &mut impl rand::Rng,
+    ) -> Vec<Selection> {
+        let count = self.transitions.len();
+        (0..batch_size)
+            .filter_map(|_| {
+                let mut builder = rs_voir::ReservoirBuilder::<Option<usize>>::default();
+                for _ in 0..candidate_count {
+                    let index = random.gen_range(0..count);
+                    let priority = self.transitions[index].priority;
+                    builder.stream_indexed(index, 1.0 / count as f32, priority, random);
+                }
+                let selected = builder.selected_index();
+                let reservoir = builder.finish();
+                selected.map(|index| Selection {
+                    index,
+                    // contribution weight estimates 1/p(index),
+                    // while uniform sampling would have p = 1/count
+                    weight: reservoir.contribution_weight() / count as f32,
+                })
+            })
+            .collect()
+    }
+}
+
+fn main() {
+    let mut random = rand::rngs::StdRng::seed_from_u64(0);
+    let mut buffer = ReplayBuffer::new(1000);
+    let arrivals_per_step = 10;
+    let batch_size = 32;
+    let candidate_count = 8;
+    let learning_rate = 0.3;
+    let step_count = 2000;
+
+    println!("step\ttrue mean loss\tweighted batch loss\tmean weight");
+    let mut estimate_sum = 0.0;
+    let mut true_sum = 0.0;
+    for step in 1..=step_count {
+        for _ in 0..arrivals_per_step {
+            // some transitions are much more surprising than others
+            let loss = random.gen::<f32>().powi(3) * 10.0;
+            buffer.push(loss);
+        }
+
+        let batch = buffer.sample(batch_size, candidate_count, &mut random);
+        let count = buffer.transitions.len() as f32;
+        let weighted_loss = batch
+            .iter()
+            .map(|selection| selection.weight * buffer.transitions[selection.index].loss)
+            .sum::<f32>()
+            / batch_size as f32;
+        let mean_weight =
+            batch.iter().map(|selection| selection.weight).sum::<f32>() / batch_size as f32;
+        let true_loss = buffer.transitions.iter().map(|t| t.loss).sum::<f32>() / count;
+        estimate_sum += weighted_loss;
+        true_sum += true_loss;
+
+        // learn from the batch: the loss shrinks and becomes the new priority
+        for selection in batch.iter() {
+            let transition = &mut buffer.transitions[selection.index];
+            transition.loss *= 1.0 - learning_rate;
+            let priority = transition.loss + 0.01;
+            buffer.update_priority(selection.index, priority);
+        }
+
+        if step % 200 == 0 {
+            println!(
+                "{}\t{:.4}\t\t{:.4}\t\t\t{:.4}",
+                step, true_loss, weighted_loss, mean_weight
+            );
+        }
+    }
+    println!(
+        "Average over all steps: true loss {:.4}, weighted batch loss {:.4}",
+        true_sum / step_count as f32,
+        estimate_sum / step_count as f32
+    );
+}
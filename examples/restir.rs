@@ -7,61 +7,83 @@ The 2D world consists of:
     - sun (a unit sphere at specified location)
     - sky (the rest of the hemisphere)
 
+Optionally, the occluder also acts as a diffuse reflector,
+and the ground receives the light bounced off it.
+This indirect light is resampled separately, ReSTIR GI style,
+with the hit point on the occluder serving as a reconnection vertex.
+
 This example implements ReSTIR for this world,
 and shows the averaged (over time) brightness
 for each point on the ground.
 
-Note: unlike Nvidia's RTXDI, the sun in this example isn't
-represented as a light source. Instead, it's a part of the environment.
-This is for demonstration purposes only.
-!*/
+The sun can be moved with the arrow keys, and the occluder
+with `[` and `]`. Both can also be dragged with the mouse. Pressing `a` toggles a scripted animation
+of both, which exposes the temporal failure modes (ghosting, lag)
+that a static scene hides. The bounced light is toggled with `g`.
+Pressing `m` runs a second pixel buffer with the unbiased precise mode
+side by side, showing the brightness of both and their difference.
+The convergence chart at the bottom can be paused with `p`,
+and the accumulation restarted with `r`. Pressing `x` spreads the pixel
+updates across threads, with every pixel using its own random stream
+derived from the frame seed, so that the results don't depend on the scheduling.
+Debug builds check this by running the serial update alongside.
 
-use std::{ops::Range, time::Duration};
+The ground truth brightness is computed by a dense quadrature
+over the hemisphere, and the relative error of every ground cell
+is shown as a heatmap: green under 5%, yellow under 20%, red otherwise.
 
-struct Output {
-    terminal: tui::Terminal<tui::backend::CrosstermBackend<std::io::Stdout>>,
-}
+The layout follows the terminal size. When the world is wider
+than the terminal, the view can be scrolled with `h` and `l`.
 
-impl Output {
-    fn grab() -> Result<Self, std::io::Error> {
-        use crossterm::ExecutableCommand as _;
+Clicking on the ground (or pressing `<` and `>`) selects a cell
+for the inspector, which shows the internals of its reservoir,
+and where the selected sample came from over the last frames:
+`i` for the initial samples, `t` for the temporal reuse,
+`<` and `>` for the spatial neighbors, and `.` for nothing.
 
-        let mut stdout = std::io::stdout();
-        stdout.execute(crossterm::terminal::EnterAlternateScreen)?;
-        stdout.execute(crossterm::event::EnableMouseCapture)?;
-        crossterm::terminal::enable_raw_mode()?;
+The example can also run without the terminal UI, for example:
+`cargo run --example restir -- --headless --frames 500 --out metrics.csv`.
+This writes per-frame deviation, mean brightness, error, and timing as CSV
+(or as JSON if the output file has a `.json` extension), so that
+different configurations can be compared. Other options are:
+    - `--convergence <index>` selects the convergence mode
+    - `--samples <count>` sets the number of initial samples
+    - `--temporal` and `--spatial` enable the reuse passes
+    - `--animate` enables the scripted animation
+    - `--indirect` enables the bounced light
+    - `--parallel` spreads the pixel updates across threads
+    - `--precision <full|ftz|half>` emulates the GPU precision of the reservoirs,
+      with the denormals flushed to zero, or in half floats
+    - `--telemetry <file>` writes the statistics of every frame as JSON lines:
+      mean history, invalidated samples, boiling pixels, and deviation
 
-        let backend = tui::backend::CrosstermBackend::new(stdout);
-        let mut terminal = tui::Terminal::new(backend)?;
-        terminal.hide_cursor()?;
-        Ok(Self { terminal })
-    }
+A session can be recorded with `--record <file>`, capturing the seed
+of the random generator, the options, and all the input, so that an
+interesting run (e.g. one showing boiling) can be replayed deterministically
+with `--play <file>`, either in the terminal UI or headless.
 
-    fn release(&mut self) -> Result<(), std::io::Error> {
-        use crossterm::ExecutableCommand as _;
+Long accumulations can be checkpointed with `--save-state <file>`,
+which writes the reservoirs, the accumulation buffers, and the scene
+on exit, and resumed with `--load-state <file>`. Since the random
+generators are derived from the seed and the update count, the resumed
+run continues exactly as the uninterrupted one would.
 
-        if std::thread::panicking() {
-            // give the opportunity to see the result
-            let _ = crossterm::event::read();
-        }
+Note: unlike Nvidia's RTXDI, the sun in this example isn't
+represented as a light source. Instead, it's a part of the environment.
+This is for demonstration purposes only.
+!*/
 
-        crossterm::terminal::disable_raw_mode()?;
-        self.terminal
-            .backend_mut()
-            .execute(crossterm::event::DisableMouseCapture)?;
-        self.terminal
-            .backend_mut()
-            .execute(crossterm::terminal::LeaveAlternateScreen)?;
-        self.terminal.show_cursor()?;
-        Ok(())
-    }
-}
+use std::{
+    cell::Cell,
+    collections::VecDeque,
+    fs, io,
+    ops::Range,
+    path::{Path, PathBuf},
+    time::{Duration, Instant},
+};
 
-impl Drop for Output {
-    fn drop(&mut self) {
-        let _ = self.release();
-    }
-}
+mod common;
+use common::Output;
 
 struct WorldConfig {
     surface_length: u16,
@@ -70,6 +92,38 @@ struct WorldConfig {
     sky_color: [f32; 3],
     occluder_y: u16,
     occluder_x: Range<u16>,
+    occluder_albedo: f32,
+}
+
+/// Scripted motion of the scene elements.
+struct Animation {
+    enabled: bool,
+    /// Number of frames between consecutive moves.
+    period: usize,
+    sun_direction: i32,
+    occluder_direction: i32,
+}
+
+impl Animation {
+    fn step(&mut self, world: &mut WorldConfig, frame_index: usize) {
+        if !self.enabled || !frame_index.is_multiple_of(self.period) {
+            return;
+        }
+
+        let sun_x = world.sun_position[0] as i32 + self.sun_direction;
+        if sun_x < 0 || sun_x >= world.surface_length as i32 {
+            self.sun_direction = -self.sun_direction;
+        }
+        world.move_sun(self.sun_direction, 0);
+
+        let occluder_start = world.occluder_x.start as i32 + self.occluder_direction;
+        if occluder_start < 0
+            || world.occluder_x.end as i32 + self.occluder_direction > world.surface_length as i32
+        {
+            self.occluder_direction = -self.occluder_direction;
+        }
+        world.move_occluder(self.occluder_direction);
+    }
 }
 
 #[derive(Clone, Default)]
@@ -82,73 +136,134 @@ impl SampleInfo {
     /// Perform a "shift mapping" of one domain to another.
     fn shift_map(&self, src_origin: glam::Vec2, dst_origin: glam::Vec2) -> glam::Vec2 {
         match self.light.distance {
-            Some(distance) => (src_origin + distance * self.dir - dst_origin).normalize(),
+            Some(distance) => {
+                let connection = rs_voir::shift::Connection {
+                    dir: self.dir.to_array(),
+                    distance,
+                };
+                let shifted = rs_voir::shift::reconnect(
+                    src_origin.to_array(),
+                    &connection,
+                    dst_origin.to_array(),
+                );
+                glam::Vec2::from(shifted.dir)
+            }
             None => self.dir,
         }
     }
 }
 
-#[derive(Default)]
+/// Sample of the indirect light, represented by the reconnection vertex on the occluder.
+///
+/// The sample lives in the area measure of the vertex, so reusing it across pixels
+/// doesn't need an explicit Jacobian of the reconnection shift: the geometry term
+/// of the target function accounts for it.
+#[derive(Clone, Default)]
+struct GiSample {
+    position: glam::Vec2,
+    radiance: glam::Vec3,
+}
+
+impl GiSample {
+    /// Convert the area measure at the vertex into the angle measure at the receiver.
+    fn geometry(&self, receiver: glam::Vec2) -> f32 {
+        let diff = self.position - receiver;
+        // the cosine is taken against the bottom side of the occluder
+        if diff.y > 0.0 {
+            diff.y / diff.length_squared()
+        } else {
+            0.0
+        }
+    }
+
+    fn target_value(&self, receiver: glam::Vec2) -> f32 {
+        rs_voir::target::norm(self.radiance.to_array()) * self.geometry(receiver)
+    }
+}
+
+/// Where the selected sample of a pixel came from in the last frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+enum SampleSource {
+    #[default]
+    None,
+    Initial,
+    Temporal,
+    Spatial {
+        offset: i8,
+    },
+}
+
+impl SampleSource {
+    fn symbol(self) -> char {
+        match self {
+            Self::None => '.',
+            Self::Initial => 'i',
+            Self::Temporal => 't',
+            Self::Spatial { offset } if offset < 0 => '<',
+            Self::Spatial { .. } => '>',
+        }
+    }
+}
+
+#[derive(Clone, Default)]
 struct Pixel {
-    reservoir: rs_voir::Reservoir,
-    selected_sample: SampleInfo,
+    reservoir: rs_voir::Reservoir<SampleInfo>,
+    source: SampleSource,
+    /// The selected sample turned out to be occluded at the end of the frame.
+    invalidated: bool,
+    gi_reservoir: rs_voir::Reservoir<GiSample>,
     color: glam::Vec3,
-    color_accumulated: glam::Vec3,
-    variance_accumulated: f32,
+    accumulated: rs_voir::Accumulator<3>,
+}
+
+impl Pixel {
+    fn color_accumulated(&self) -> glam::Vec3 {
+        glam::Vec3::from(self.accumulated.mean())
+    }
 }
 
 struct WorldView<'a> {
     config: &'a WorldConfig,
     pixels: &'a [Pixel],
+    /// Index of the first visible ground cell.
+    scroll: u16,
+    inspected: Option<u16>,
 }
 impl tui::widgets::Widget for WorldView<'_> {
     fn render(self, area: tui::layout::Rect, buf: &mut tui::buffer::Buffer) {
         use tui::style::Color;
 
-        if area.height == 0 {
-            return;
-        }
+        // Put a symbol at the given world coordinates, unless it's out of view.
+        let mut put = |x: u16, y: u16, symbol: &str, color: Color| {
+            if x >= self.scroll && x - self.scroll < area.width && y < area.height {
+                let column = area.x + x - self.scroll;
+                let row = area.y + area.height - 1 - y;
+                buf.get_mut(column, row).set_symbol(symbol).set_fg(color);
+            }
+        };
 
-        let bottom = area.y + area.height - 1;
-        {
-            let cell_index = (bottom - self.config.sun_position[1]) * buf.area.width
-                + self.config.sun_position[0]
-                + area.x;
-            buf.content[cell_index as usize] = tui::buffer::Cell {
-                symbol: "*".to_string(),
-                fg: Color::Yellow,
-                ..Default::default()
-            };
-        }
+        let [sun_x, sun_y] = self.config.sun_position;
+        put(sun_x, sun_y, "*", Color::Yellow);
 
         for x in self.config.occluder_x.clone() {
-            let cell_index = (bottom - self.config.occluder_y) * buf.area.width + x + area.x;
-            buf.content[cell_index as usize] = tui::buffer::Cell {
-                symbol: "=".to_string(),
-                fg: Color::Blue,
-                ..Default::default()
-            };
+            put(x, self.config.occluder_y, "=", Color::Blue);
         }
 
         for x in 0..self.config.surface_length {
-            let cell_index = bottom * buf.area.width + x + area.x;
-            buf.content[cell_index as usize] = tui::buffer::Cell {
-                symbol: "-".to_string(),
-                fg: Color::Green,
-                ..Default::default()
+            let color = if self.inspected == Some(x) {
+                Color::Magenta
+            } else {
+                Color::Green
             };
+            put(x, 0, "-", color);
             if self.pixels[x as usize].color.length() > 1.0 {
-                buf.content[(cell_index - buf.area.width) as usize] = tui::buffer::Cell {
-                    symbol: ",".to_string(),
-                    fg: Color::White,
-                    ..Default::default()
-                };
+                put(x, 1, ",", Color::White);
             }
         }
     }
 }
 
-#[derive(Clone, Copy, Debug)]
+#[derive(Clone, Copy, Debug, PartialEq)]
 enum Convergence {
     /// Check visibility of every sample taken.
     Precise { unbias: bool },
@@ -156,18 +271,50 @@ enum Convergence {
     LeanAndMean { initial_visibility: bool },
 }
 
+impl Convergence {
+    fn describe(&self) -> &'static str {
+        match *self {
+            Self::Precise { unbias: false } => "Precise",
+            Self::Precise { unbias: true } => "Precise, unbiased",
+            Self::LeanAndMean {
+                initial_visibility: false,
+            } => "Lean&Mean",
+            Self::LeanAndMean {
+                initial_visibility: true,
+            } => "Lean&Mean, initial visibility",
+        }
+    }
+}
+
+/// Convergence modes, in the order they are cycled through.
+const CONVERGENCE_MODES: [Convergence; 4] = [
+    Convergence::LeanAndMean {
+        initial_visibility: false,
+    },
+    Convergence::LeanAndMean {
+        initial_visibility: true,
+    },
+    Convergence::Precise { unbias: false },
+    Convergence::Precise { unbias: true },
+];
+
 struct RestirConfig {
     convergence: Convergence,
     initial_samples: u32,
     max_initial_history: u32,
     max_temporal_history: u32,
     max_spatial_history: u32,
+    indirect: bool,
 }
 
 struct Config {
     world: WorldConfig,
     restir: RestirConfig,
     accumulation: f32,
+    /// Spread the pixel updates across threads.
+    parallel: bool,
+    /// Emulated precision of the reservoir arithmetic.
+    precision: rs_voir::Precision,
 }
 
 #[derive(Clone, Default)]
@@ -180,22 +327,83 @@ impl LightInfo {
     /// Returns the value we assign to this light, based on the color (or intensity).
     /// This is also known as "unnormalized target PDF" in literature.
     fn target_value(&self) -> f32 {
-        self.color.length()
+        rs_voir::target::norm(self.color.to_array())
     }
 }
 
 impl WorldConfig {
-    fn get_incoming_light(&self, origin: glam::Vec2, dir: glam::Vec2) -> LightInfo {
-        debug_assert!(dir.is_normalized());
-        let sun_pos = glam::vec2(
+    const MAX_SUN_HEIGHT: u16 = 20;
+
+    fn place_sun(&mut self, x: i32, y: i32) {
+        self.sun_position = [
+            x.clamp(0, self.surface_length as i32 - 1) as u16,
+            y.clamp(1, Self::MAX_SUN_HEIGHT as i32) as u16,
+        ];
+    }
+
+    fn move_sun(&mut self, dx: i32, dy: i32) {
+        let [x, y] = self.sun_position;
+        self.place_sun(x as i32 + dx, y as i32 + dy);
+    }
+
+    fn place_occluder(&mut self, start: i32, y: i32) {
+        let width = self.occluder_x.end - self.occluder_x.start;
+        let start = start.clamp(0, (self.surface_length - width) as i32) as u16;
+        self.occluder_x = start..start + width;
+        self.occluder_y = y.clamp(1, Self::MAX_SUN_HEIGHT as i32) as u16;
+    }
+
+    fn move_occluder(&mut self, dx: i32) {
+        self.place_occluder(self.occluder_x.start as i32 + dx, self.occluder_y as i32);
+    }
+
+    /// Height of the world view, in cells.
+    fn view_height(&self) -> u16 {
+        self.sun_position[1].max(self.occluder_y) + 1
+    }
+
+    const SUN_RADIUS: f32 = 0.5;
+
+    fn sun_center(&self) -> glam::Vec2 {
+        glam::vec2(
             self.sun_position[0] as f32 + 0.5,
             self.sun_position[1] as f32 + 0.5,
-        );
-        let diff = sun_pos - origin;
+        )
+    }
+
+    /// Compute the expected brightness of every ground cell with a dense quadrature
+    /// over the hemisphere.
+    fn compute_reference(&self, indirect: bool, direction_count: u32) -> Vec<glam::Vec3> {
+        use std::f32::consts::PI;
+
+        let step = PI / direction_count as f32;
+        (0..self.surface_length)
+            .map(|cell_index| {
+                let surface_pos = glam::vec2(cell_index as f32 + 0.5, 0.0);
+                let mut sum = glam::Vec3::ZERO;
+                for i in 0..direction_count {
+                    let alpha = (i as f32 + 0.5) * step;
+                    let dir = glam::vec2(alpha.cos(), alpha.sin());
+                    if self.check_visibility(surface_pos, dir) {
+                        sum += self.get_incoming_light(surface_pos, dir).color;
+                    }
+                    if indirect {
+                        if let Some(position) = self.hit_occluder(surface_pos, dir) {
+                            sum += self.occluder_radiance(position);
+                        }
+                    }
+                }
+                sum * step
+            })
+            .collect()
+    }
+
+    fn get_incoming_light(&self, origin: glam::Vec2, dir: glam::Vec2) -> LightInfo {
+        debug_assert!(dir.is_normalized());
+        let diff = self.sun_center() - origin;
         let sun_distance = diff.dot(dir);
         let leftover = diff - sun_distance * dir;
-        let sun_radius = 0.5;
-        if leftover.length_squared() < sun_radius * sun_radius {
+        if leftover.length_squared() < Self::SUN_RADIUS * Self::SUN_RADIUS {
             LightInfo {
                 color: self.sun_color.into(),
                 distance: Some(sun_distance),
@@ -217,182 +425,833 @@ impl WorldConfig {
         }
         let t = (self.occluder_y as f32 + 0.5 - origin.y) / dir.y;
         let x = origin.x + dir.x * t;
-        x < self.occluder_x.start as f32 || x > self.occluder_x.end as f32
+        !self.is_on_occluder(x)
+    }
+
+    fn is_on_occluder(&self, x: f32) -> bool {
+        x >= self.occluder_x.start as f32 && x <= self.occluder_x.end as f32
+    }
+
+    /// Returns the point where the ray hits the bottom side of the occluder.
+    fn hit_occluder(&self, origin: glam::Vec2, dir: glam::Vec2) -> Option<glam::Vec2> {
+        let y = self.occluder_y as f32 + 0.5;
+        if dir.y <= 0.0 || origin.y >= y {
+            return None;
+        }
+        let x = origin.x + dir.x * (y - origin.y) / dir.y;
+        if self.is_on_occluder(x) {
+            Some(glam::vec2(x, y))
+        } else {
+            None
+        }
+    }
+
+    /// Returns the radiance leaving the occluder at a given point.
+    ///
+    /// For simplicity, the occluder is a thin diffuse sheet that scatters
+    /// the sunlight it receives to both of its sides.
+    fn occluder_radiance(&self, point: glam::Vec2) -> glam::Vec3 {
+        if !self.is_on_occluder(point.x) {
+            return glam::Vec3::ZERO;
+        }
+        let diff = self.sun_center() - point;
+        let distance = diff.length();
+        let cosine = diff.y.abs() / distance;
+        let subtended_angle = 2.0 * Self::SUN_RADIUS / distance;
+        let irradiance = glam::Vec3::from(self.sun_color) * cosine * subtended_angle;
+        // a Lambertian lobe in 2D integrates to 2
+        0.5 * self.occluder_albedo * irradiance
+    }
+}
+
+/// Run the function for every pixel, optionally spreading the work across threads.
+///
+/// Every pixel gets its own random stream, so the results
+/// are the same regardless of the scheduling.
+fn for_each_pixel(
+    pixels: &mut [Pixel],
+    seed: u32,
+    parallel: bool,
+    fun: impl Fn(usize, &mut Pixel, &mut rs_voir::RandomStream) + Sync,
+) {
+    if !parallel {
+        for (index, pixel) in pixels.iter_mut().enumerate() {
+            fun(
+                index,
+                pixel,
+                &mut rs_voir::RandomStream::new(index as u32, seed),
+            );
+        }
+        return;
+    }
+
+    let thread_count = std::thread::available_parallelism().map_or(1, |count| count.get());
+    let chunk_size = pixels.len().div_ceil(thread_count).max(1);
+    let fun = &fun;
+    std::thread::scope(|scope| {
+        for (chunk_index, chunk) in pixels.chunks_mut(chunk_size).enumerate() {
+            scope.spawn(move || {
+                for (i, pixel) in chunk.iter_mut().enumerate() {
+                    let index = chunk_index * chunk_size + i;
+                    fun(
+                        index,
+                        pixel,
+                        &mut rs_voir::RandomStream::new(index as u32, seed),
+                    );
+                }
+            });
+        }
+    });
+}
+
+/// Buffer of pixels, resampled with a particular convergence mode.
+#[derive(Default)]
+struct Layer {
+    pixels: Box<[Pixel]>,
+    smooth_avg_deviation: f32,
+}
+
+impl Layer {
+    /// First random dimensions of the reuse passes, see `RandomStream`.
+    /// The initial candidates use the dimensions below.
+    const TEMPORAL_DIMENSION: u32 = 1 << 16;
+    const SPATIAL_DIMENSION: u32 = Self::TEMPORAL_DIMENSION + 16;
+    /// How many times a pixel has to be brighter than its accumulated color
+    /// to be counted as boiling.
+    const BOILING_RATIO: f32 = 10.0;
+
+    fn new(config: &Config) -> Self {
+        let mode = rs_voir::AccumulationMode::Exponential(config.accumulation);
+        Self {
+            pixels: (0..config.world.surface_length)
+                .map(|_| Pixel {
+                    accumulated: rs_voir::Accumulator::new(mode),
+                    ..Pixel::default()
+                })
+                .collect(),
+            smooth_avg_deviation: 0.0,
+        }
+    }
+
+    fn update(
+        &mut self,
+        config: &Config,
+        convergence: Convergence,
+        random: &mut rand::rngs::StdRng,
+    ) {
+        use rand::Rng;
+
+        // Back up the current information before re-using
+        let backup = self
+            .pixels
+            .iter()
+            .map(|pixel| pixel.reservoir.clone())
+            .collect::<Vec<_>>();
+        let gi_backup = self
+            .pixels
+            .iter()
+            .map(|pixel| pixel.gi_reservoir.clone())
+            .collect::<Vec<_>>();
+        let seeds = [random.gen::<u32>(), random.gen::<u32>()];
+
+        let resample = |pixels: &mut [Pixel], parallel: bool| {
+            for_each_pixel(pixels, seeds[0], parallel, |cell_index, pixel, random| {
+                Self::resample_direct(config, convergence, &backup, cell_index, pixel, random)
+            });
+            if config.restir.indirect {
+                for_each_pixel(pixels, seeds[1], parallel, |cell_index, pixel, random| {
+                    Self::resample_indirect(config, &gi_backup, cell_index, pixel, random)
+                });
+            }
+        };
+        if cfg!(debug_assertions) && config.parallel {
+            // make sure the parallel update doesn't depend on the scheduling
+            let mut serial_pixels = self.pixels.clone();
+            resample(&mut serial_pixels, false);
+            resample(&mut self.pixels, true);
+            assert!(
+                serial_pixels
+                    .iter()
+                    .zip(self.pixels.iter())
+                    .all(|(a, b)| a.color == b.color),
+                "Parallel update diverged from the serial one"
+            );
+        } else {
+            resample(&mut self.pixels, config.parallel);
+        }
+
+        for pixel in self.pixels.iter_mut() {
+            pixel.accumulated.add(pixel.color.to_array());
+        }
+
+        let sum_variance = self
+            .pixels
+            .iter()
+            .map(|pixel| pixel.accumulated.variance())
+            .sum::<f32>();
+        let std_deviation = (sum_variance / self.pixels.len() as f32).sqrt();
+        self.smooth_avg_deviation = self.smooth_avg_deviation * (1.0 - config.accumulation)
+            + config.accumulation * std_deviation;
+    }
+
+    /// Resample the direct light of a single pixel.
+    fn resample_direct(
+        config: &Config,
+        convergence: Convergence,
+        backup: &[rs_voir::Reservoir<SampleInfo>],
+        cell_index: usize,
+        pixel: &mut Pixel,
+        random: &mut rs_voir::RandomStream,
+    ) {
+        let surface_pos = glam::vec2(cell_index as f32 + 0.5, 0.0);
+        let mut builder = rs_voir::ReservoirBuilder::default().with_precision(config.precision);
+        let mut source = SampleSource::None;
+        let mut invalidated = false;
+
+        // First, do RIS on the initial samples
+        for _ in 0..config.restir.initial_samples {
+            // generate a random direction in the hemisphere
+            let (dir, source_pdf) = rs_voir::sampling::uniform_semicircle(random);
+            let dir = glam::Vec2::from(dir);
+            let is_visible = match convergence {
+                Convergence::Precise { .. } => config.world.check_visibility(surface_pos, dir),
+                Convergence::LeanAndMean { .. } => true,
+            };
+            if is_visible {
+                let light = config.world.get_incoming_light(surface_pos, dir);
+                let target_value = light.target_value();
+                if builder.stream(source_pdf, target_value, SampleInfo { dir, light }, random) {
+                    source = SampleSource::Initial;
+                }
+            } else {
+                builder.add_empty_sample();
+            }
+        }
+
+        if let Convergence::LeanAndMean {
+            initial_visibility: true,
+            ..
+        } = convergence
+        {
+            if !config
+                .world
+                .check_visibility(surface_pos, builder.selected().dir)
+            {
+                builder.selected_mut().light = LightInfo::default();
+                invalidated = true;
+            }
+        }
+        builder.clamp_history(config.restir.max_initial_history);
+
+        // Second, reuse the previous frame reservoir.
+        random.set_dimension(Self::TEMPORAL_DIMENSION);
+        if config.restir.max_temporal_history != 0 {
+            let prev = backup[cell_index].with_max_history(config.restir.max_temporal_history);
+            if prev.has_weight() {
+                let other = prev.to_builder(prev.sample().light.target_value());
+                if builder.merge(&other, random) {
+                    source = SampleSource::Temporal;
+                }
+            } else {
+                builder.merge_history(&prev);
+            }
+        }
+
+        // Third, reuse the previous frame neighboring reservoirs
+        random.set_dimension(Self::SPATIAL_DIMENSION);
+        let mut unbiased_history = builder.history();
+        if config.restir.max_spatial_history != 0 {
+            let offsets = [-1, 1];
+            let neighbor_pos = offsets.map(|offset| surface_pos + glam::vec2(offset as f32, 0.0));
+            let mut neighbors = [rs_voir::NeighborInput::default(), Default::default()];
+            for (i, &offset) in offsets.iter().enumerate() {
+                let index = cell_index as isize + offset;
+                if index < 0 || index >= config.world.surface_length as isize {
+                    continue;
+                }
+                let prev =
+                    backup[index as usize].with_max_history(config.restir.max_spatial_history);
+                let prev_sample = prev.sample();
+                let surface_dir = prev_sample.shift_map(neighbor_pos[i], surface_pos);
+                let is_visible = match convergence {
+                    Convergence::Precise { .. } => {
+                        config.world.check_visibility(surface_pos, surface_dir)
+                    }
+                    Convergence::LeanAndMean { .. } => true,
+                };
+                neighbors[i] = rs_voir::NeighborInput {
+                    target_pdf: if is_visible && prev.has_weight() {
+                        prev_sample.light.target_value()
+                    } else {
+                        0.0
+                    },
+                    reservoir: prev.with_sample(SampleInfo {
+                        dir: surface_dir,
+                        light: prev_sample.light.clone(),
+                    }),
+                };
+            }
+
+            let merge = builder.merge_neighbors(neighbors, random);
+            if let Some(i) = merge.selected {
+                source = SampleSource::Spatial {
+                    offset: offsets[i] as i8,
+                };
+            }
+
+            // Post-factum reject reservoirs that couldn't have produced this sample.
+            unbiased_history = match convergence {
+                Convergence::Precise { unbias: true } => merge.unbiased_history(|i| {
+                    let other_dir = builder.selected().shift_map(surface_pos, neighbor_pos[i]);
+                    config.world.check_visibility(neighbor_pos[i], other_dir)
+                }),
+                _ => builder.history(),
+            };
+        }
+
+        if let Convergence::LeanAndMean { .. } = convergence {
+            let selected = builder.selected_mut();
+            if selected.light.target_value() > 0.0
+                && !config.world.check_visibility(surface_pos, selected.dir)
+            {
+                selected.light = LightInfo::default();
+                invalidated = true;
+            }
+        }
+
+        // Finally write out the results
+        pixel.reservoir = builder.finish_with_history(unbiased_history);
+        pixel.source = source;
+        pixel.invalidated = invalidated;
+        pixel.color = pixel.reservoir.sample().light.color * pixel.reservoir.contribution_weight();
+    }
+
+    /// Resample the light bounced off the occluder, adding it to the pixel color.
+    fn resample_indirect(
+        config: &Config,
+        backup: &[rs_voir::Reservoir<GiSample>],
+        cell_index: usize,
+        pixel: &mut Pixel,
+        random: &mut rs_voir::RandomStream,
+    ) {
+        let restir = &config.restir;
+        let world = &config.world;
+        let surface_pos = glam::vec2(cell_index as f32 + 0.5, 0.0);
+        let mut builder = rs_voir::ReservoirBuilder::default().with_precision(config.precision);
+
+        for _ in 0..restir.initial_samples {
+            let (dir, dir_pdf) = rs_voir::sampling::uniform_semicircle(random);
+            let dir = glam::Vec2::from(dir);
+            match world.hit_occluder(surface_pos, dir) {
+                Some(position) => {
+                    let sample = GiSample {
+                        position,
+                        radiance: world.occluder_radiance(position),
+                    };
+                    // convert the PDF of the direction into the area measure
+                    let source_pdf = sample.geometry(surface_pos) * dir_pdf;
+                    let target_value = sample.target_value(surface_pos);
+                    builder.stream(source_pdf, target_value, sample, random);
+                }
+                None => builder.add_empty_sample(),
+            }
+        }
+        builder.clamp_history(restir.max_initial_history);
+
+        let mut sources = Vec::with_capacity(3);
+        if restir.max_temporal_history != 0 {
+            sources.push((cell_index, restir.max_temporal_history));
+        }
+        if restir.max_spatial_history != 0 {
+            for offset in [-1, 1] {
+                let index = cell_index as isize + offset;
+                if index >= 0 && index < world.surface_length as isize {
+                    sources.push((index as usize, restir.max_spatial_history));
+                }
+            }
+        }
+
+        random.set_dimension(Self::TEMPORAL_DIMENSION);
+        for (index, max_history) in sources {
+            let prev = backup[index].with_max_history(max_history);
+            // the scene may have changed, so re-evaluate the vertex
+            let position = prev.sample().position;
+            let sample = GiSample {
+                position,
+                radiance: world.occluder_radiance(position),
+            };
+            let target_value = sample.target_value(surface_pos);
+            if prev.has_weight() && target_value > 0.0 {
+                let other = prev.with_sample(sample).to_builder(target_value);
+                builder.merge(&other, random);
+            } else {
+                builder.merge_history(&prev);
+            }
+        }
+
+        pixel.gi_reservoir = builder.finish();
+        let selected = pixel.gi_reservoir.sample();
+        pixel.color += selected.radiance
+            * selected.geometry(surface_pos)
+            * pixel.gi_reservoir.contribution_weight();
+    }
+
+    /// Root mean square error of the accumulated brightness against the reference.
+    fn error(&self, reference: &Reference) -> f32 {
+        let sum = self
+            .pixels
+            .iter()
+            .zip(reference.colors.iter())
+            .map(|(pixel, expected)| (pixel.color_accumulated() - *expected).length_squared())
+            .sum::<f32>();
+        (sum / self.pixels.len() as f32).sqrt()
+    }
+
+    fn mean_brightness(&self) -> f32 {
+        let sum = self
+            .pixels
+            .iter()
+            .map(|pixel| pixel.color_accumulated().length())
+            .sum::<f32>();
+        sum / self.pixels.len() as f32
+    }
+
+    /// Write the aggregate statistics of the last update as a line of JSON.
+    fn write_telemetry(&self, out: &mut impl io::Write, frame_index: usize) -> io::Result<()> {
+        let history_sum = self
+            .pixels
+            .iter()
+            .map(|pixel| pixel.reservoir.history())
+            .sum::<u32>();
+        let invalidations = self.pixels.iter().filter(|pixel| pixel.invalidated).count();
+        let boiling = self
+            .pixels
+            .iter()
+            .filter(|pixel| {
+                let accumulated = pixel.color_accumulated().length().max(0.05);
+                pixel.color.length() > Self::BOILING_RATIO * accumulated
+            })
+            .count();
+        writeln!(
+            out,
+            "{{\"frame\": {}, \"mean_history\": {}, \"invalidations\": {}, \"boiling\": {}, \"deviation\": {}}}",
+            frame_index,
+            history_sum as f32 / self.pixels.len() as f32,
+            invalidations,
+            boiling,
+            self.smooth_avg_deviation,
+        )
+    }
+}
+
+/// Ground truth brightness of the ground, for the scene state it was computed with.
+#[derive(Default)]
+struct Reference {
+    key: Option<([u16; 2], Range<u16>, u16, bool)>,
+    colors: Vec<glam::Vec3>,
+}
+
+impl Reference {
+    fn update(&mut self, config: &Config) {
+        let world = &config.world;
+        let key = (
+            world.sun_position,
+            world.occluder_x.clone(),
+            world.occluder_y,
+            config.restir.indirect,
+        );
+        if self.key.as_ref() != Some(&key) {
+            self.colors = world.compute_reference(config.restir.indirect, 4096);
+            self.key = Some(key);
+        }
+    }
+
+    /// Relative error of the accumulated brightness of a pixel.
+    fn relative_error(&self, cell_index: usize, pixel: &Pixel) -> f32 {
+        let expected = self.colors[cell_index].length();
+        (pixel.color_accumulated().length() - expected).abs() / expected.max(0.05)
+    }
+}
+
+struct ErrorView<'a> {
+    reference: &'a Reference,
+    pixels: &'a [Pixel],
+    scroll: u16,
+}
+impl tui::widgets::Widget for ErrorView<'_> {
+    fn render(self, area: tui::layout::Rect, buf: &mut tui::buffer::Buffer) {
+        use tui::style::Color;
+
+        if area.height == 0 || self.reference.colors.is_empty() {
+            return;
+        }
+        let visible = self
+            .pixels
+            .iter()
+            .enumerate()
+            .skip(self.scroll as usize)
+            .take(area.width as usize);
+        for (column, (cell_index, pixel)) in visible.enumerate() {
+            let error = self.reference.relative_error(cell_index, pixel);
+            let color = if error < 0.05 {
+                Color::Green
+            } else if error < 0.2 {
+                Color::Yellow
+            } else {
+                Color::Red
+            };
+            let cell = buf.get_mut(area.x + column as u16, area.y);
+            cell.set_symbol("█").set_fg(color);
+        }
+    }
+}
+
+/// Live view into the reservoir of a single ground cell.
+struct Inspector {
+    cell: u16,
+    /// Sources of the selected samples over the last frames.
+    acceptance: VecDeque<SampleSource>,
+}
+
+impl Inspector {
+    const HISTORY: usize = 24;
+
+    fn new(cell: u16) -> Self {
+        Self {
+            cell,
+            acceptance: VecDeque::with_capacity(Self::HISTORY),
+        }
+    }
+
+    fn record(&mut self, pixel: &Pixel) {
+        if self.acceptance.len() == Self::HISTORY {
+            self.acceptance.pop_front();
+        }
+        self.acceptance.push_back(pixel.source);
+    }
+}
+
+/// Scene element being dragged by the mouse,
+/// with the offset from the cursor to its origin.
+enum Drag {
+    Sun { offset: [i32; 2] },
+    Occluder { offset: [i32; 2] },
+}
+
+/// Per-frame statistics of the main layer, used for charting the convergence.
+#[derive(Default)]
+struct ConvergenceHistory {
+    deviation: Vec<(f64, f64)>,
+    brightness: Vec<(f64, f64)>,
+}
+
+impl ConvergenceHistory {
+    const CAPACITY: usize = 1000;
+
+    fn push(&mut self, frame_index: usize, layer: &Layer) {
+        if self.deviation.len() == Self::CAPACITY {
+            self.deviation.drain(..Self::CAPACITY / 2);
+            self.brightness.drain(..Self::CAPACITY / 2);
+        }
+        let x = frame_index as f64;
+        self.deviation.push((x, layer.smooth_avg_deviation as f64));
+        self.brightness.push((x, layer.mean_brightness() as f64));
+    }
+}
+
+struct Render {
+    config: Config,
+    animation: Animation,
+    main: Layer,
+    /// Optional second layer, resampled with a different convergence mode.
+    comparison: Option<(Convergence, Layer)>,
+    history: ConvergenceHistory,
+    reference: Reference,
+    /// Seed of the random generators, which are derived from it every update.
+    seed: u64,
+    /// Number of updates done, unlike the frame index it's never reset.
+    tick: usize,
+    frame_index: usize,
+    paused: bool,
+    /// Screen area of the world view, as of the last draw.
+    world_area: Cell<tui::layout::Rect>,
+    /// Index of the first visible ground cell, if the world doesn't fit the screen.
+    scroll: u16,
+    inspector: Option<Inspector>,
+    /// Output for the per-frame statistics, as JSON lines.
+    telemetry: Option<io::BufWriter<fs::File>>,
+}
+impl Render {
+    const TEMPORAL_HISTORY: u32 = 20;
+    const SPATIAL_HISTORY: u32 = 10;
+    const COMPARE_CONVERGENCE: Convergence = Convergence::Precise { unbias: true };
+
+    /// Convert screen coordinates into the world cell coordinates.
+    fn world_position(&self, column: u16, row: u16) -> Option<[i32; 2]> {
+        let area = self.world_area.get();
+        if area.height == 0 {
+            return None;
+        }
+        let bottom = (area.y + area.height - 1) as i32;
+        Some([
+            column as i32 - area.x as i32 + self.scroll as i32,
+            bottom - row as i32,
+        ])
+    }
+
+    /// Find the scene element under the cursor.
+    fn pick(&self, column: u16, row: u16) -> Option<Drag> {
+        let [x, y] = self.world_position(column, row)?;
+        let world = &self.config.world;
+        let sun = world.sun_position;
+        if (x - sun[0] as i32).abs() <= 1 && (y - sun[1] as i32).abs() <= 1 {
+            Some(Drag::Sun {
+                offset: [sun[0] as i32 - x, sun[1] as i32 - y],
+            })
+        } else if y == world.occluder_y as i32
+            && x >= world.occluder_x.start as i32
+            && x < world.occluder_x.end as i32
+        {
+            Some(Drag::Occluder {
+                offset: [
+                    world.occluder_x.start as i32 - x,
+                    world.occluder_y as i32 - y,
+                ],
+            })
+        } else {
+            None
+        }
+    }
+
+    fn drag(&self, drag: &Drag, column: u16, row: u16) -> Option<Action> {
+        let [x, y] = self.world_position(column, row)?;
+        Some(match *drag {
+            Drag::Sun { offset } => Action::PlaceSun([x + offset[0], y + offset[1]]),
+            Drag::Occluder { offset } => Action::PlaceOccluder([x + offset[0], y + offset[1]]),
+        })
+    }
+
+    fn apply(&mut self, action: &Action) {
+        let restir = &mut self.config.restir;
+        match *action {
+            Action::Key('c') => {
+                let index = CONVERGENCE_MODES
+                    .iter()
+                    .position(|&mode| mode == restir.convergence)
+                    .map_or(0, |index| index + 1);
+                restir.convergence = CONVERGENCE_MODES[index % CONVERGENCE_MODES.len()];
+            }
+            Action::Key(',') if restir.initial_samples != 0 => {
+                restir.initial_samples -= 1;
+            }
+            Action::Key('.') => {
+                restir.initial_samples += 1;
+            }
+            Action::Key('s') => {
+                restir.max_spatial_history = if restir.max_spatial_history == 0 {
+                    Self::SPATIAL_HISTORY
+                } else {
+                    0
+                };
+            }
+            Action::Key('t') => {
+                restir.max_temporal_history = if restir.max_temporal_history == 0 {
+                    Self::TEMPORAL_HISTORY
+                } else {
+                    0
+                };
+            }
+            Action::Key('g') => {
+                restir.indirect ^= true;
+            }
+            Action::Key('x') => {
+                self.config.parallel ^= true;
+            }
+            Action::Key('<') => self.move_inspector(-1),
+            Action::Key('>') => self.move_inspector(1),
+            Action::Key('h') => self.scroll_by(-1),
+            Action::Key('l') => self.scroll_by(1),
+            Action::Key('p') => {
+                self.paused ^= true;
+            }
+            Action::Key('r') => {
+                self.reset();
+            }
+            Action::Key('m') => {
+                self.comparison = match self.comparison {
+                    Some(_) => None,
+                    None => Some((Self::COMPARE_CONVERGENCE, Layer::new(&self.config))),
+                };
+            }
+            Action::Key('a') => {
+                self.animation.enabled ^= true;
+            }
+            Action::Key('[') => self.config.world.move_occluder(-1),
+            Action::Key(']') => self.config.world.move_occluder(1),
+            Action::Key(_) => {}
+            Action::MoveSun([dx, dy]) => self.config.world.move_sun(dx, dy),
+            Action::PlaceSun([x, y]) => self.config.world.place_sun(x, y),
+            Action::PlaceOccluder([x, y]) => self.config.world.place_occluder(x, y),
+            Action::Inspect(cell) => {
+                self.inspector = Some(Inspector::new(cell));
+            }
+        }
+    }
+
+    fn update(&mut self) {
+        use rand::SeedableRng as _;
+
+        self.frame_index += 1;
+        self.tick += 1;
+        self.animation
+            .step(&mut self.config.world, self.frame_index);
+
+        let mut random =
+            rand::rngs::StdRng::seed_from_u64(self.seed.wrapping_add(self.tick as u64));
+        self.main
+            .update(&self.config, self.config.restir.convergence, &mut random);
+        if let Some((convergence, ref mut layer)) = self.comparison {
+            layer.update(&self.config, convergence, &mut random);
+        }
+        self.reference.update(&self.config);
+        self.history.push(self.frame_index, &self.main);
+        if let Some(ref mut inspector) = self.inspector {
+            inspector.record(&self.main.pixels[inspector.cell as usize]);
+        }
+        if let Some(ref mut out) = self.telemetry {
+            self.main
+                .write_telemetry(out, self.frame_index)
+                .expect("Unable to write the telemetry");
+        }
+    }
+
+    /// Select the ground cell under the cursor for inspection.
+    fn inspect_at(&self, column: u16, row: u16) -> Option<Action> {
+        let [x, _] = self.world_position(column, row)?;
+        if x >= 0 && x < self.config.world.surface_length as i32 {
+            Some(Action::Inspect(x as u16))
+        } else {
+            None
+        }
     }
-}
 
-struct Render {
-    config: Config,
-    pixels: Box<[Pixel]>,
-    random: rand::rngs::ThreadRng,
-    frame_index: usize,
-    smooth_avg_deviation: f32,
-}
-impl Render {
-    fn update(&mut self) {
-        use rand::Rng;
-        use std::f32::consts::PI;
+    fn move_inspector(&mut self, delta: i32) {
+        let cell = match self.inspector {
+            Some(ref inspector) => inspector.cell as i32 + delta,
+            None => 0,
+        };
+        let max_cell = self.config.world.surface_length as i32 - 1;
+        self.inspector = Some(Inspector::new(cell.clamp(0, max_cell) as u16));
+    }
 
-        self.frame_index += 1;
+    fn scroll_by(&mut self, delta: i32) {
+        let visible = self.world_area.get().width;
+        let max_scroll = self.config.world.surface_length.saturating_sub(visible);
+        self.scroll = (self.scroll as i32 + delta).clamp(0, max_scroll as i32) as u16;
+    }
 
-        // Back up the current information before re-using
-        let backup = self
-            .pixels
-            .iter()
-            .map(|pixel| (pixel.reservoir.clone(), pixel.selected_sample.clone()))
-            .collect::<Vec<_>>();
+    const STATE_MAGIC: &'static [u8; 4] = b"VOIR";
+    const STATE_VERSION: u32 = 3;
 
-        for (cell_index, pixel) in self.pixels.iter_mut().enumerate() {
-            let surface_pos = glam::vec2(cell_index as f32 + 0.5, 0.0);
-            let mut builder = rs_voir::ReservoirBuilder::default();
-            let mut selected = SampleInfo::default();
-
-            // First, do RIS on the initial samples
-            for _ in 0..self.config.restir.initial_samples {
-                // generate a random direction in the hemisphere
-                let alpha = self.random.gen_range(0.0..=PI);
-                let dir = glam::vec2(alpha.cos(), alpha.sin());
-                let is_visible = match self.config.restir.convergence {
-                    Convergence::Precise { .. } => {
-                        self.config.world.check_visibility(surface_pos, dir)
-                    }
-                    Convergence::LeanAndMean { .. } => true,
-                };
-                if is_visible {
-                    let light = self.config.world.get_incoming_light(surface_pos, dir);
-                    if builder.stream(1.0 / PI, light.target_value(), &mut self.random) {
-                        selected = SampleInfo { dir, light };
-                    }
-                } else {
-                    builder.add_empty_sample();
-                }
-            }
+    /// Save the full simulation state, so that a long accumulation can be resumed.
+    ///
+    /// The state is a little-endian binary dump, with the floats stored by their bits,
+    /// and the random generators are derived from the seed and the update count,
+    /// so the resumed run continues bit-consistently.
+    fn save_state(&self, path: &Path) -> io::Result<()> {
+        let mut writer = StateWriter {
+            data: Self::STATE_MAGIC.to_vec(),
+        };
+        writer.u32(Self::STATE_VERSION);
+        writer.u64(self.seed);
+        writer.u64(self.tick as u64);
+        writer.u64(self.frame_index as u64);
 
-            if let Convergence::LeanAndMean {
-                initial_visibility: true,
-                ..
-            } = self.config.restir.convergence
-            {
-                if !self
-                    .config
-                    .world
-                    .check_visibility(surface_pos, selected.dir)
-                {
-                    selected.light = LightInfo::default();
-                }
-            }
-            builder.clamp_history(self.config.restir.max_initial_history);
+        let world = &self.config.world;
+        writer.u32(world.surface_length as u32);
+        writer.u32(world.sun_position[0] as u32);
+        writer.u32(world.sun_position[1] as u32);
+        writer.u32(world.occluder_y as u32);
+        writer.u32(world.occluder_x.start as u32);
+        writer.u32(world.occluder_x.end as u32);
 
-            // Second, reuse the previous frame reservoir.
-            if self.config.restir.max_temporal_history != 0 {
-                let (ref prev_reservoir, ref prev_sample) = backup[cell_index];
-                let prev = prev_reservoir.with_max_history(self.config.restir.max_temporal_history);
-                if prev.has_weight() {
-                    let other = prev.to_builder(prev_sample.light.target_value());
-                    if builder.merge(&other, &mut self.random) {
-                        selected = prev_sample.clone();
-                    }
-                } else {
-                    builder.merge_history(&prev);
-                }
-            }
+        let restir = &self.config.restir;
+        writer.convergence(restir.convergence);
+        writer.u32(restir.initial_samples);
+        writer.u32(restir.max_initial_history);
+        writer.u32(restir.max_temporal_history);
+        writer.u32(restir.max_spatial_history);
+        writer.bool(restir.indirect);
 
-            // Third, reuse the previous frame neighboring reservoirs
-            let mut unbiased_history = builder.history();
-            if self.config.restir.max_spatial_history != 0 {
-                let mut selected_cell = -1;
-                for offset in [-1, 1] {
-                    let index = cell_index as isize + offset;
-                    if index < 0 || index >= self.config.world.surface_length as isize {
-                        continue;
-                    }
-                    let (ref prev_reservoir, ref prev_sample) = backup[index as usize];
-                    let prev =
-                        prev_reservoir.with_max_history(self.config.restir.max_spatial_history);
-                    let other_pos = surface_pos + glam::vec2(offset as f32, 0.0);
-
-                    if prev.has_weight() {
-                        let surface_dir = prev_sample.shift_map(other_pos, surface_pos);
-                        let is_visible = match self.config.restir.convergence {
-                            Convergence::Precise { .. } => {
-                                self.config.world.check_visibility(surface_pos, surface_dir)
-                            }
-                            Convergence::LeanAndMean { .. } => true,
-                        };
-                        if is_visible {
-                            let other = prev.to_builder(prev_sample.light.target_value());
-                            if builder.merge(&other, &mut self.random) {
-                                selected = SampleInfo {
-                                    dir: surface_dir,
-                                    light: prev_sample.light.clone(),
-                                };
-                                selected_cell = index;
-                            }
-                        } else {
-                            builder.merge_history(&prev);
-                        }
-                    } else {
-                        builder.merge_history(&prev);
-                    }
-                }
+        writer.bool(self.animation.enabled);
+        writer.u32(self.animation.sun_direction as u32);
+        writer.u32(self.animation.occluder_direction as u32);
 
-                // Post-factum reject reservoirs that couldn't have produced this sample.
-                if let Convergence::Precise { unbias: true } = self.config.restir.convergence {
-                    for offset in [-1, 1] {
-                        let index = cell_index as isize + offset;
-                        if index < 0 || index >= self.config.world.surface_length as isize {
-                            continue;
-                        }
-                        let (ref prev_reservoir, _) = backup[index as usize];
-                        let covers_domain = if index == selected_cell {
-                            true
-                        } else {
-                            let other_pos = surface_pos + glam::vec2(offset as f32, 0.0);
-                            let other_dir = selected.shift_map(surface_pos, other_pos);
-                            self.config.world.check_visibility(other_pos, other_dir)
-                        };
-                        if covers_domain {
-                            unbiased_history += prev_reservoir
-                                .with_max_history(self.config.restir.max_spatial_history)
-                                .history();
-                        }
-                    }
-                } else {
-                    unbiased_history = builder.history();
-                }
-            }
+        writer.layer(&self.main);
+        writer.bool(self.comparison.is_some());
+        if let Some((convergence, ref layer)) = self.comparison {
+            writer.convergence(convergence);
+            writer.layer(layer);
+        }
+        fs::write(path, writer.data)
+    }
 
-            if let Convergence::LeanAndMean { .. } = self.config.restir.convergence {
-                if selected.light.target_value() > 0.0
-                    && !self
-                        .config
-                        .world
-                        .check_visibility(surface_pos, selected.dir)
-                {
-                    selected.light = LightInfo::default();
-                }
-            }
+    /// Load the simulation state saved by `save_state`.
+    fn load_state(&mut self, path: &Path) -> io::Result<()> {
+        let invalid = |message| io::Error::new(io::ErrorKind::InvalidData, message);
+        let data = fs::read(path)?;
+        let mut reader = match data.strip_prefix(Self::STATE_MAGIC) {
+            Some(data) => StateReader { data },
+            None => return Err(invalid("Not a state file")),
+        };
+        if reader.u32()? != Self::STATE_VERSION {
+            return Err(invalid("Unsupported state version"));
+        }
+        self.seed = reader.u64()?;
+        self.tick = reader.u64()? as usize;
+        self.frame_index = reader.u64()? as usize;
 
-            // Finally write out the results
-            pixel.reservoir = builder.finish_with_history(unbiased_history);
-            pixel.selected_sample = selected;
-            pixel.color = pixel.selected_sample.light.color * pixel.reservoir.contribution_weight();
-            let variance = (pixel.color - pixel.color_accumulated).length_squared();
-            pixel.variance_accumulated = pixel.variance_accumulated
-                * (1.0 - self.config.accumulation)
-                + self.config.accumulation * variance;
-            pixel.color_accumulated = pixel.color_accumulated * (1.0 - self.config.accumulation)
-                + self.config.accumulation * pixel.color;
+        let world = &mut self.config.world;
+        let surface_length = reader.u32()? as u16;
+        if surface_length != world.surface_length {
+            return Err(invalid("Mismatching surface length"));
         }
+        world.sun_position = [reader.u32()? as u16, reader.u32()? as u16];
+        world.occluder_y = reader.u32()? as u16;
+        world.occluder_x = reader.u32()? as u16..reader.u32()? as u16;
 
-        let sum_variance = self
-            .pixels
-            .iter()
-            .map(|pixel| pixel.variance_accumulated)
-            .sum::<f32>();
-        let std_deviation = (sum_variance / self.pixels.len() as f32).sqrt();
-        self.smooth_avg_deviation = self.smooth_avg_deviation * (1.0 - self.config.accumulation)
-            + self.config.accumulation * std_deviation;
+        let restir = &mut self.config.restir;
+        restir.convergence = reader.convergence()?;
+        restir.initial_samples = reader.u32()?;
+        restir.max_initial_history = reader.u32()?;
+        restir.max_temporal_history = reader.u32()?;
+        restir.max_spatial_history = reader.u32()?;
+        restir.indirect = reader.bool()?;
+
+        self.animation.enabled = reader.bool()?;
+        self.animation.sun_direction = reader.u32()? as i32;
+        self.animation.occluder_direction = reader.u32()? as i32;
+
+        self.main = reader.layer(&self.config)?;
+        self.comparison = if reader.bool()? {
+            let convergence = reader.convergence()?;
+            Some((convergence, reader.layer(&self.config)?))
+        } else {
+            None
+        };
+        self.history = ConvergenceHistory::default();
+        self.reference.update(&self.config);
+        Ok(())
+    }
+
+    /// Start accumulating from scratch.
+    fn reset(&mut self) {
+        self.main = Layer::new(&self.config);
+        if let Some((_, ref mut layer)) = self.comparison {
+            *layer = Layer::new(&self.config);
+        }
+        self.history = ConvergenceHistory::default();
+        self.frame_index = 0;
     }
 
     fn draw<B: tui::backend::Backend>(&self, frame: &mut tui::Frame<B>) {
@@ -403,13 +1262,13 @@ impl Render {
             widgets as w,
         };
 
-        fn make_key_value(key: &str, value: String) -> Spans {
+        fn make_key_value(key: &str, value: String) -> Spans<'_> {
             Spans(vec![
                 Span::styled(key, Style::default().fg(Color::DarkGray)),
                 Span::raw(value),
             ])
         }
-        fn make_key_bool(key: &str, value: bool) -> Spans {
+        fn make_key_bool(key: &str, value: bool) -> Spans<'_> {
             let (color, value_str) = if value {
                 (Color::Green, "on")
             } else {
@@ -421,6 +1280,10 @@ impl Render {
             ])
         }
 
+        let outer_rects = l::Layout::default()
+            .direction(l::Direction::Vertical)
+            .constraints([l::Constraint::Min(20), l::Constraint::Length(14)].as_ref())
+            .split(frame.size());
         let top_hor_rects = l::Layout::default()
             .direction(l::Direction::Horizontal)
             .constraints(
@@ -431,13 +1294,14 @@ impl Render {
                 .as_ref(),
             )
             .margin(1)
-            .split(frame.size());
+            .split(outer_rects[0]);
 
         let top_vl_rects = l::Layout::default()
             .direction(l::Direction::Vertical)
             .constraints(
                 [
-                    l::Constraint::Length((self.config.world.sun_position[1] + 3) as _),
+                    l::Constraint::Length((self.config.world.view_height() + 2) as _),
+                    l::Constraint::Length(3),
                     l::Constraint::Min(10),
                 ]
                 .as_ref(),
@@ -446,39 +1310,97 @@ impl Render {
             .split(top_hor_rects[0]);
         let top_vr_rects = l::Layout::default()
             .direction(l::Direction::Vertical)
-            .constraints([l::Constraint::Length(5), l::Constraint::Length(10)].as_ref())
+            .constraints(
+                [
+                    l::Constraint::Length(5),
+                    l::Constraint::Length(13),
+                    l::Constraint::Length(10),
+                ]
+                .as_ref(),
+            )
             .margin(1)
             .split(top_hor_rects[1]);
 
         let world_block = w::Block::default().borders(w::Borders::ALL).title("World");
         let inner = world_block.inner(top_vl_rects[0]);
+        self.world_area.set(inner);
         frame.render_widget(world_block, top_vl_rects[0]);
         frame.render_widget(
             WorldView {
                 config: &self.config.world,
-                pixels: &self.pixels,
+                pixels: &self.main.pixels,
+                scroll: self.scroll,
+                inspected: self.inspector.as_ref().map(|inspector| inspector.cell),
+            },
+            inner,
+        );
+
+        let error_block = w::Block::default()
+            .borders(w::Borders::ALL)
+            .title(format!("Error {:.3}", self.main.error(&self.reference)));
+        let inner = error_block.inner(top_vl_rects[1]);
+        frame.render_widget(error_block, top_vl_rects[1]);
+        frame.render_widget(
+            ErrorView {
+                reference: &self.reference,
+                pixels: &self.main.pixels,
+                scroll: self.scroll,
             },
             inner,
         );
 
         let brightness_scale = 100u64;
-        let brightness = self
-            .pixels
-            .iter()
-            .map(|pixel| (pixel.color_accumulated.length() * brightness_scale as f32) as u64)
-            .collect::<Vec<_>>();
-        let brightness_block = w::Sparkline::default()
-            .block(
-                w::Block::default()
-                    .title(format!("Brightness / {}", brightness_scale))
-                    .borders(w::Borders::ALL),
-            )
-            .data(&brightness)
-            .max(brightness_scale * 2);
-        frame.render_widget(brightness_block, top_vl_rects[1]);
+        let to_brightness = |pixels: &[Pixel]| {
+            pixels
+                .iter()
+                .map(|pixel| pixel.color_accumulated().length())
+                .collect::<Vec<_>>()
+        };
+        let mut graphs = vec![(
+            format!("Brightness / {}", brightness_scale),
+            to_brightness(&self.main.pixels),
+        )];
+        if let Some((convergence, ref layer)) = self.comparison {
+            graphs[0].0 += &format!(" ({})", self.config.restir.convergence.describe());
+            let other = to_brightness(&layer.pixels);
+            let difference = graphs[0]
+                .1
+                .iter()
+                .zip(other.iter())
+                .map(|(a, b)| (a - b).abs())
+                .collect();
+            graphs.push((
+                format!(
+                    "Brightness / {} ({})",
+                    brightness_scale,
+                    convergence.describe()
+                ),
+                other,
+            ));
+            graphs.push((format!("Difference / {}", brightness_scale), difference));
+        }
+        let graph_rects = l::Layout::default()
+            .direction(l::Direction::Vertical)
+            .constraints(vec![
+                l::Constraint::Ratio(1, graphs.len() as u32);
+                graphs.len()
+            ])
+            .split(top_vl_rects[2]);
+        for ((title, values), rect) in graphs.into_iter().zip(graph_rects) {
+            let data = values
+                .iter()
+                .skip(self.scroll as usize)
+                .map(|value| (value * brightness_scale as f32) as u64)
+                .collect::<Vec<_>>();
+            let brightness_block = w::Sparkline::default()
+                .block(w::Block::default().title(title).borders(w::Borders::ALL))
+                .data(&data)
+                .max(brightness_scale * 2);
+            frame.render_widget(brightness_block, rect);
+        }
 
         let max_deviation = 5.0;
-        let deviation_color = if self.smooth_avg_deviation < 1.0 {
+        let deviation_color = if self.main.smooth_avg_deviation < 1.0 {
             Color::Green
         } else {
             Color::Red
@@ -490,8 +1412,8 @@ impl Render {
                     .borders(w::Borders::ALL),
             )
             .gauge_style(Style::default().fg(deviation_color).bg(Color::Black))
-            .label(format!("{:.2}", self.smooth_avg_deviation))
-            .ratio((self.smooth_avg_deviation / max_deviation).min(1.0) as f64);
+            .label(format!("{:.2}", self.main.smooth_avg_deviation))
+            .ratio((self.main.smooth_avg_deviation / max_deviation).min(1.0) as f64);
         frame.render_widget(deviation_block, top_vr_rects[0]);
 
         let mut text = vec![
@@ -524,31 +1446,615 @@ impl Render {
                 text.push(make_key_bool("Initial visibility: ", initial_visibility));
             }
         }
+        text.push(make_key_bool("Animation: ", self.animation.enabled));
+        text.push(make_key_bool("Indirect: ", self.config.restir.indirect));
+        text.push(make_key_bool("Comparison: ", self.comparison.is_some()));
+        text.push(make_key_bool("Parallel: ", self.config.parallel));
+        if self.config.precision != rs_voir::Precision::Full {
+            text.push(make_key_value(
+                "Precision: ",
+                format!("{:?}", self.config.precision),
+            ));
+        }
         let text_block = w::Paragraph::new(text)
             .block(w::Block::default().title("Info").borders(w::Borders::ALL))
             .wrap(w::Wrap { trim: true });
         frame.render_widget(text_block, top_vr_rects[1]);
+
+        let inspector_text = match self.inspector {
+            Some(ref inspector) => {
+                let pixel = &self.main.pixels[inspector.cell as usize];
+                let reservoir = &pixel.reservoir;
+                let sample = reservoir.sample();
+                let target_value = sample.light.target_value();
+                let light = match sample.light.distance {
+                    _ if target_value == 0.0 => "none",
+                    Some(_) => "sun",
+                    None => "sky",
+                };
+                let angle = sample.dir.y.atan2(sample.dir.x);
+                vec![
+                    make_key_value("Cell: ", format!("{}", inspector.cell)),
+                    make_key_value("History: ", format!("{}", reservoir.history())),
+                    make_key_value(
+                        "Weight sum: ",
+                        format!(
+                            "{:.3}",
+                            reservoir.contribution_weight()
+                                * reservoir.history() as f32
+                                * target_value
+                        ),
+                    ),
+                    make_key_value(
+                        "Contribution: ",
+                        format!("{:.3}", reservoir.contribution_weight()),
+                    ),
+                    make_key_value(
+                        "Selected: ",
+                        format!("{} at {:.0}°", light, angle.to_degrees()),
+                    ),
+                    make_key_value(
+                        "Accepted: ",
+                        inspector
+                            .acceptance
+                            .iter()
+                            .map(|source| source.symbol())
+                            .collect(),
+                    ),
+                ]
+            }
+            None => vec![Spans::from("Click on the ground")],
+        };
+        let inspector_block = w::Paragraph::new(inspector_text)
+            .block(
+                w::Block::default()
+                    .title("Inspector")
+                    .borders(w::Borders::ALL),
+            )
+            .wrap(w::Wrap { trim: true });
+        frame.render_widget(inspector_block, top_vr_rects[2]);
+
+        let (x_start, x_end) = match (
+            self.history.deviation.first(),
+            self.history.deviation.last(),
+        ) {
+            (Some(first), Some(last)) => (first.0, last.0.max(first.0 + 1.0)),
+            _ => (0.0, 1.0),
+        };
+        let y_max = self
+            .history
+            .deviation
+            .iter()
+            .chain(self.history.brightness.iter())
+            .fold(1.0f64, |max, &(_, y)| max.max(y));
+        let datasets = vec![
+            w::Dataset::default()
+                .name("Std deviation")
+                .marker(tui::symbols::Marker::Braille)
+                .graph_type(w::GraphType::Line)
+                .style(Style::default().fg(Color::Red))
+                .data(&self.history.deviation),
+            w::Dataset::default()
+                .name("Mean brightness")
+                .marker(tui::symbols::Marker::Braille)
+                .graph_type(w::GraphType::Line)
+                .style(Style::default().fg(Color::Yellow))
+                .data(&self.history.brightness),
+        ];
+        let title = if self.paused {
+            "Convergence (paused)"
+        } else {
+            "Convergence"
+        };
+        let chart = w::Chart::new(datasets)
+            .block(w::Block::default().title(title).borders(w::Borders::ALL))
+            .x_axis(
+                w::Axis::default()
+                    .title("Frame")
+                    .bounds([x_start, x_end])
+                    .labels(vec![
+                        Span::raw(format!("{}", x_start)),
+                        Span::raw(format!("{}", x_end)),
+                    ]),
+            )
+            .y_axis(
+                w::Axis::default()
+                    .bounds([0.0, y_max])
+                    .labels(vec![Span::raw("0"), Span::raw(format!("{:.1}", y_max))]),
+            );
+        frame.render_widget(chart, outer_rects[1]);
+    }
+}
+
+/// User input affecting the simulation, which can be recorded and played back.
+///
+/// Mouse input is resolved into world coordinates before recording,
+/// so that the playback doesn't depend on the terminal layout.
+#[derive(Clone, Debug, PartialEq)]
+enum Action {
+    Key(char),
+    MoveSun([i32; 2]),
+    PlaceSun([i32; 2]),
+    PlaceOccluder([i32; 2]),
+    Inspect(u16),
+}
+
+impl Action {
+    fn parse(text: &str) -> Option<Self> {
+        let mut words = text.split_whitespace();
+        let kind = words.next()?;
+        let mut number = || words.next()?.parse::<i32>().ok();
+        Some(match kind {
+            "key" => Self::Key(text["key".len()..].trim().chars().next()?),
+            "move-sun" => Self::MoveSun([number()?, number()?]),
+            "place-sun" => Self::PlaceSun([number()?, number()?]),
+            "place-occluder" => Self::PlaceOccluder([number()?, number()?]),
+            "inspect" => Self::Inspect(number()? as u16),
+            _ => return None,
+        })
+    }
+}
+
+impl std::fmt::Display for Action {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match *self {
+            Self::Key(key) => write!(f, "key {}", key),
+            Self::MoveSun([dx, dy]) => write!(f, "move-sun {} {}", dx, dy),
+            Self::PlaceSun([x, y]) => write!(f, "place-sun {} {}", x, y),
+            Self::PlaceOccluder([x, y]) => write!(f, "place-occluder {} {}", x, y),
+            Self::Inspect(cell) => write!(f, "inspect {}", cell),
+        }
+    }
+}
+
+/// Session being written to a file.
+///
+/// The file starts with the seed of the random generator and the command
+/// line arguments, followed by the actions, each prefixed with the number
+/// of updates done before it. Since the random generator is seeded,
+/// this is enough to reproduce every random decision of the run.
+struct Recording {
+    out: io::BufWriter<fs::File>,
+}
+
+impl Recording {
+    fn create(path: &Path, seed: u64, args: &[String]) -> io::Result<Self> {
+        use std::io::Write as _;
+        let mut out = io::BufWriter::new(fs::File::create(path)?);
+        writeln!(out, "seed {}", seed)?;
+        writeln!(out, "args\t{}", args.join("\t"))?;
+        Ok(Self { out })
+    }
+
+    fn record(&mut self, tick: usize, action: &Action) -> io::Result<()> {
+        use std::io::Write as _;
+        writeln!(self.out, "{} {}", tick, action)?;
+        // keep the file useful even if the example is killed
+        self.out.flush()
+    }
+}
+
+/// Session read from a file, see `Recording`.
+struct Playback {
+    seed: u64,
+    args: Vec<String>,
+    actions: VecDeque<(usize, Action)>,
+}
+
+impl Playback {
+    fn load(path: &Path) -> io::Result<Self> {
+        let invalid = |line: &str| {
+            io::Error::new(
+                io::ErrorKind::InvalidData,
+                format!("Invalid session line: {}", line),
+            )
+        };
+        let text = fs::read_to_string(path)?;
+        let mut lines = text.lines();
+        let seed = lines
+            .next()
+            .and_then(|line| line.strip_prefix("seed ")?.parse().ok())
+            .ok_or_else(|| invalid("seed"))?;
+        let args = lines
+            .next()
+            .and_then(|line| line.strip_prefix("args"))
+            .ok_or_else(|| invalid("args"))?
+            .split('\t')
+            .filter(|arg| !arg.is_empty())
+            .map(String::from)
+            .collect();
+        let actions = lines
+            .map(|line| {
+                line.split_once(' ')
+                    .and_then(|(tick, action)| Some((tick.parse().ok()?, Action::parse(action)?)))
+                    .ok_or_else(|| invalid(line))
+            })
+            .collect::<io::Result<_>>()?;
+        Ok(Self {
+            seed,
+            args,
+            actions,
+        })
+    }
+
+    /// Take the next action recorded before the given number of updates.
+    fn next(&mut self, tick: usize) -> Option<Action> {
+        match self.actions.front() {
+            Some(&(action_tick, _)) if action_tick <= tick => {
+                self.actions.pop_front().map(|(_, action)| action)
+            }
+            _ => None,
+        }
+    }
+}
+
+/// Writer of the simulation state, see `Render::save_state`.
+struct StateWriter {
+    data: Vec<u8>,
+}
+
+impl StateWriter {
+    fn u32(&mut self, value: u32) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+    fn u64(&mut self, value: u64) {
+        self.data.extend_from_slice(&value.to_le_bytes());
+    }
+    fn bool(&mut self, value: bool) {
+        self.u32(value as u32);
+    }
+    fn f32(&mut self, value: f32) {
+        self.u32(value.to_bits());
+    }
+    fn f64(&mut self, value: f64) {
+        self.u64(value.to_bits());
+    }
+    fn vec2(&mut self, value: glam::Vec2) {
+        for v in value.to_array() {
+            self.f32(v);
+        }
+    }
+    fn vec3(&mut self, value: glam::Vec3) {
+        for v in value.to_array() {
+            self.f32(v);
+        }
+    }
+    fn convergence(&mut self, convergence: Convergence) {
+        let index = CONVERGENCE_MODES
+            .iter()
+            .position(|&mode| mode == convergence)
+            .unwrap();
+        self.u32(index as u32);
+    }
+    fn reservoir<S>(&mut self, reservoir: &rs_voir::Reservoir<S>) {
+        self.u32(reservoir.history());
+        self.f32(reservoir.contribution_weight());
+        self.f32(reservoir.target_pdf());
+    }
+    fn sample_info(&mut self, sample: &SampleInfo) {
+        self.vec2(sample.dir);
+        self.vec3(sample.light.color);
+        self.bool(sample.light.distance.is_some());
+        self.f32(sample.light.distance.unwrap_or_default());
+    }
+    fn gi_sample(&mut self, sample: &GiSample) {
+        self.vec2(sample.position);
+        self.vec3(sample.radiance);
+    }
+    fn accumulator(&mut self, accumulator: &rs_voir::Accumulator<3>) {
+        let (mean, variance, count) = accumulator.to_parts();
+        for v in mean {
+            self.f64(v);
+        }
+        self.f64(variance);
+        self.u64(count);
+    }
+
+    fn layer(&mut self, layer: &Layer) {
+        self.u32(layer.pixels.len() as u32);
+        for pixel in layer.pixels.iter() {
+            self.reservoir(&pixel.reservoir);
+            self.sample_info(pixel.reservoir.sample());
+            self.reservoir(&pixel.gi_reservoir);
+            self.gi_sample(pixel.gi_reservoir.sample());
+            self.vec3(pixel.color);
+            self.accumulator(&pixel.accumulated);
+        }
+        self.f32(layer.smooth_avg_deviation);
+    }
+}
+
+/// Reader of the simulation state, see `Render::load_state`.
+struct StateReader<'a> {
+    data: &'a [u8],
+}
+
+impl StateReader<'_> {
+    fn u32(&mut self) -> io::Result<u32> {
+        if self.data.len() < 4 {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+        let (head, tail) = self.data.split_at(4);
+        self.data = tail;
+        Ok(u32::from_le_bytes(head.try_into().unwrap()))
+    }
+    fn u64(&mut self) -> io::Result<u64> {
+        Ok(self.u32()? as u64 | (self.u32()? as u64) << 32)
+    }
+    fn bool(&mut self) -> io::Result<bool> {
+        Ok(self.u32()? != 0)
+    }
+    fn f32(&mut self) -> io::Result<f32> {
+        Ok(f32::from_bits(self.u32()?))
+    }
+    fn f64(&mut self) -> io::Result<f64> {
+        Ok(f64::from_bits(self.u64()?))
+    }
+    fn vec2(&mut self) -> io::Result<glam::Vec2> {
+        Ok(glam::vec2(self.f32()?, self.f32()?))
+    }
+    fn vec3(&mut self) -> io::Result<glam::Vec3> {
+        Ok(glam::vec3(self.f32()?, self.f32()?, self.f32()?))
+    }
+    fn convergence(&mut self) -> io::Result<Convergence> {
+        let index = self.u32()? as usize;
+        CONVERGENCE_MODES
+            .get(index)
+            .cloned()
+            .ok_or_else(|| io::Error::new(io::ErrorKind::InvalidData, "Unknown convergence mode"))
+    }
+    fn reservoir<S>(
+        &mut self,
+        read_sample: impl FnOnce(&mut Self) -> io::Result<S>,
+    ) -> io::Result<rs_voir::Reservoir<S>> {
+        let history = self.u32()?;
+        let contribution_weight = self.f32()?;
+        let target_pdf = self.f32()?;
+        let sample = read_sample(self)?;
+        Ok(rs_voir::Reservoir::from_parts(
+            history,
+            contribution_weight,
+            target_pdf,
+            sample,
+        ))
+    }
+    fn sample_info(&mut self) -> io::Result<SampleInfo> {
+        let dir = self.vec2()?;
+        let color = self.vec3()?;
+        let has_distance = self.bool()?;
+        let distance = self.f32()?;
+        Ok(SampleInfo {
+            dir,
+            light: LightInfo {
+                color,
+                distance: Some(distance).filter(|_| has_distance),
+            },
+        })
+    }
+    fn gi_sample(&mut self) -> io::Result<GiSample> {
+        Ok(GiSample {
+            position: self.vec2()?,
+            radiance: self.vec3()?,
+        })
+    }
+    fn accumulator(
+        &mut self,
+        mode: rs_voir::AccumulationMode,
+    ) -> io::Result<rs_voir::Accumulator<3>> {
+        let mean = [self.f64()?, self.f64()?, self.f64()?];
+        let parts = (mean, self.f64()?, self.u64()?);
+        Ok(rs_voir::Accumulator::from_parts(mode, parts))
+    }
+
+    fn layer(&mut self, config: &Config) -> io::Result<Layer> {
+        if self.u32()? != config.world.surface_length as u32 {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidData,
+                "Mismatching surface length",
+            ));
+        }
+        let mut layer = Layer::new(config);
+        for pixel in layer.pixels.iter_mut() {
+            pixel.reservoir = self.reservoir(Self::sample_info)?;
+            pixel.gi_reservoir = self.reservoir(Self::gi_sample)?;
+            pixel.color = self.vec3()?;
+            pixel.accumulated = self.accumulator(pixel.accumulated.mode())?;
+        }
+        layer.smooth_avg_deviation = self.f32()?;
+        Ok(layer)
+    }
+}
+
+/// Command line options.
+#[derive(Default)]
+struct Options {
+    headless: bool,
+    frames: Option<usize>,
+    out: Option<PathBuf>,
+    convergence: Option<usize>,
+    initial_samples: Option<u32>,
+    temporal: bool,
+    spatial: bool,
+    animate: bool,
+    indirect: bool,
+    record: Option<PathBuf>,
+    play: Option<PathBuf>,
+    parallel: bool,
+    precision: Option<rs_voir::Precision>,
+    telemetry: Option<PathBuf>,
+    save_state: Option<PathBuf>,
+    load_state: Option<PathBuf>,
+}
+
+impl Options {
+    fn parse(args: impl IntoIterator<Item = String>) -> Self {
+        fn value<T: std::str::FromStr>(args: &mut impl Iterator<Item = String>, name: &str) -> T {
+            args.next()
+                .and_then(|arg| arg.parse().ok())
+                .unwrap_or_else(|| panic!("Option {} expects a value", name))
+        }
+
+        let mut options = Self::default();
+        let mut args = args.into_iter();
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "--headless" => options.headless = true,
+                "--frames" => options.frames = Some(value(&mut args, &arg)),
+                "--out" => options.out = Some(value(&mut args, &arg)),
+                "--convergence" => options.convergence = Some(value(&mut args, &arg)),
+                "--samples" => options.initial_samples = Some(value(&mut args, &arg)),
+                "--temporal" => options.temporal = true,
+                "--spatial" => options.spatial = true,
+                "--animate" => options.animate = true,
+                "--indirect" => options.indirect = true,
+                "--record" => options.record = Some(value(&mut args, &arg)),
+                "--play" => options.play = Some(value(&mut args, &arg)),
+                "--parallel" => options.parallel = true,
+                "--precision" => {
+                    options.precision = Some(match args.next().as_deref() {
+                        Some("full") => rs_voir::Precision::Full,
+                        Some("ftz") => rs_voir::Precision::FlushToZero,
+                        Some("half") => rs_voir::Precision::Half,
+                        _ => panic!("Option {} expects one of: full, ftz, half", arg),
+                    })
+                }
+                "--telemetry" => options.telemetry = Some(value(&mut args, &arg)),
+                "--save-state" => options.save_state = Some(value(&mut args, &arg)),
+                "--load-state" => options.load_state = Some(value(&mut args, &arg)),
+                other => panic!("Unknown option: {}", other),
+            }
+        }
+        options
+    }
+}
+
+/// Statistics gathered for a single frame.
+struct FrameMetrics {
+    frame_index: usize,
+    deviation: f32,
+    mean_brightness: f32,
+    error: f32,
+    update_time: Duration,
+}
+
+enum MetricsFormat {
+    Csv,
+    Json,
+}
+
+fn write_metrics(
+    out: &mut impl io::Write,
+    format: MetricsFormat,
+    metrics: &[FrameMetrics],
+) -> io::Result<()> {
+    match format {
+        MetricsFormat::Csv => {
+            writeln!(out, "frame,deviation,mean_brightness,error,update_ms")?;
+            for m in metrics {
+                writeln!(
+                    out,
+                    "{},{},{},{},{}",
+                    m.frame_index,
+                    m.deviation,
+                    m.mean_brightness,
+                    m.error,
+                    m.update_time.as_secs_f64() * 1000.0
+                )?;
+            }
+        }
+        MetricsFormat::Json => {
+            writeln!(out, "[")?;
+            for (i, m) in metrics.iter().enumerate() {
+                let separator = if i + 1 == metrics.len() { "" } else { "," };
+                writeln!(
+                    out,
+                    "  {{\"frame\": {}, \"deviation\": {}, \"mean_brightness\": {}, \"error\": {}, \"update_ms\": {}}}{}",
+                    m.frame_index,
+                    m.deviation,
+                    m.mean_brightness,
+                    m.error,
+                    m.update_time.as_secs_f64() * 1000.0,
+                    separator
+                )?;
+            }
+            writeln!(out, "]")?;
+        }
+    }
+    Ok(())
+}
+
+fn run_headless(
+    render: &mut Render,
+    options: &Options,
+    mut playback: Option<Playback>,
+) -> io::Result<()> {
+    let frame_count = options.frames.unwrap_or(1000);
+    let mut metrics = Vec::with_capacity(frame_count);
+    for _ in 0..frame_count {
+        if let Some(ref mut playback) = playback {
+            while let Some(action) = playback.next(render.tick) {
+                render.apply(&action);
+            }
+        }
+        let start = Instant::now();
+        render.update();
+        metrics.push(FrameMetrics {
+            frame_index: render.frame_index,
+            deviation: render.main.smooth_avg_deviation,
+            mean_brightness: render.main.mean_brightness(),
+            error: render.main.error(&render.reference),
+            update_time: start.elapsed(),
+        });
+    }
+
+    match options.out {
+        Some(ref path) => {
+            let format = match path.extension() {
+                Some(ext) if ext == "json" => MetricsFormat::Json,
+                _ => MetricsFormat::Csv,
+            };
+            let mut file = io::BufWriter::new(fs::File::create(path)?);
+            write_metrics(&mut file, format, &metrics)
+        }
+        None => write_metrics(&mut io::stdout().lock(), MetricsFormat::Csv, &metrics),
     }
 }
 
 fn main() {
-    use crossterm::event as ev;
+    let args = std::env::args().skip(1).collect::<Vec<_>>();
+    let mut options = Options::parse(args.iter().cloned());
+    let playback = options
+        .play
+        .as_ref()
+        .map(|path| Playback::load(path).expect("Unable to load the session"));
+    let seed = match playback {
+        Some(ref playback) => {
+            // the scene setup comes from the session,
+            // while the way to run it comes from the command line
+            let recorded = Options::parse(playback.args.iter().cloned());
+            options = Options {
+                headless: options.headless,
+                frames: options.frames,
+                out: options.out,
+                parallel: options.parallel,
+                telemetry: options.telemetry,
+                save_state: options.save_state,
+                load_state: options.load_state,
+                ..recorded
+            };
+            playback.seed
+        }
+        None => rand::random(),
+    };
+    let recording = options.record.as_ref().map(|path| {
+        let mut scene_args = args.clone();
+        if let Some(index) = scene_args.iter().position(|arg| arg == "--record") {
+            scene_args.drain(index..index + 2);
+        }
+        Recording::create(path, seed, &scene_args).expect("Unable to create the session")
+    });
 
     let surface_length = 40;
-    let mut convergence_index = 0;
-    let convergence_list = &[
-        Convergence::LeanAndMean {
-            initial_visibility: false,
-        },
-        Convergence::LeanAndMean {
-            initial_visibility: true,
-        },
-        Convergence::Precise { unbias: false },
-        Convergence::Precise { unbias: true },
-    ];
-    let temporal_history = 20;
-    let spatial_history = 10;
-    let mut sun_drag_start = None;
 
     let mut render = Render {
         config: Config {
@@ -559,64 +2065,128 @@ fn main() {
                 sky_color: [0.0, 0.0, 0.1],
                 occluder_y: 5,
                 occluder_x: 7..15,
+                occluder_albedo: 0.5,
             },
             restir: RestirConfig {
-                convergence: convergence_list[convergence_index],
+                convergence: CONVERGENCE_MODES[0],
                 initial_samples: 1,
                 max_initial_history: 1,
                 max_temporal_history: 0,
                 max_spatial_history: 0,
+                indirect: false,
             },
             accumulation: 0.01,
+            parallel: false,
+            precision: rs_voir::Precision::Full,
+        },
+        animation: Animation {
+            enabled: false,
+            period: 10,
+            sun_direction: 1,
+            occluder_direction: -1,
         },
-        pixels: (0..surface_length).map(|_| Pixel::default()).collect(),
-        random: rand::thread_rng(),
+        main: Layer::default(),
+        comparison: None,
+        history: ConvergenceHistory::default(),
+        reference: Reference::default(),
+        seed,
+        tick: 0,
         frame_index: 0,
-        smooth_avg_deviation: 0.0,
+        paused: false,
+        world_area: Cell::default(),
+        scroll: 0,
+        inspector: None,
+        telemetry: options.telemetry.as_ref().map(|path| {
+            io::BufWriter::new(fs::File::create(path).expect("Unable to create the telemetry file"))
+        }),
     };
 
+    if let Some(index) = options.convergence {
+        render.config.restir.convergence = CONVERGENCE_MODES[index % CONVERGENCE_MODES.len()];
+    }
+    if let Some(count) = options.initial_samples {
+        render.config.restir.initial_samples = count;
+    }
+    if options.temporal {
+        render.config.restir.max_temporal_history = Render::TEMPORAL_HISTORY;
+    }
+    if options.spatial {
+        render.config.restir.max_spatial_history = Render::SPATIAL_HISTORY;
+    }
+    render.animation.enabled = options.animate;
+    render.config.restir.indirect = options.indirect;
+    render.config.parallel = options.parallel;
+    if let Some(precision) = options.precision {
+        render.config.precision = precision;
+    }
+    render.main = Layer::new(&render.config);
+    if let Some(ref path) = options.load_state {
+        render
+            .load_state(path)
+            .expect("Unable to load the simulation state");
+    }
+
+    if options.headless {
+        run_headless(&mut render, &options, playback).unwrap();
+    } else {
+        run_terminal(&mut render, &options, playback, recording);
+    }
+    if let Some(ref path) = options.save_state {
+        render
+            .save_state(path)
+            .expect("Unable to save the simulation state");
+    }
+}
+
+fn run_terminal(
+    render: &mut Render,
+    options: &Options,
+    mut playback: Option<Playback>,
+    mut recording: Option<Recording>,
+) {
+    use crossterm::event as ev;
+
+    let mut drag = None;
+
     let mut output = Output::grab().unwrap();
+    // counted from the start, since the frame index may come from a loaded state
+    let mut frames_rendered = 0;
     loop {
-        render.update();
+        if options.frames == Some(frames_rendered) {
+            return;
+        }
+        if let Some(ref mut playback) = playback {
+            while let Some(action) = playback.next(render.tick) {
+                render.apply(&action);
+            }
+        }
+        if !render.paused {
+            render.update();
+            frames_rendered += 1;
+        }
         output.terminal.draw(|f| render.draw(f)).unwrap();
 
         while ev::poll(Duration::ZERO).unwrap() {
-            match ev::read().unwrap() {
-                ev::Event::Resize(..) => {}
+            let action = match ev::read().unwrap() {
+                ev::Event::Resize(..) => {
+                    // the next draw adapts the layout, just keep the scroll in range
+                    render.scroll_by(0);
+                    None
+                }
                 ev::Event::Key(event) => match event.code {
                     ev::KeyCode::Esc => {
                         return;
                     }
-                    ev::KeyCode::Char('c') => {
-                        convergence_index = (convergence_index + 1) % convergence_list.len();
-                        render.config.restir.convergence = convergence_list[convergence_index];
-                    }
-                    ev::KeyCode::Char(',') => {
-                        if render.config.restir.initial_samples != 0 {
-                            render.config.restir.initial_samples -= 1;
-                        }
-                    }
-                    ev::KeyCode::Char('.') => {
-                        render.config.restir.initial_samples += 1;
-                    }
-                    ev::KeyCode::Char('s') => {
-                        render.config.restir.max_spatial_history =
-                            if render.config.restir.max_spatial_history == 0 {
-                                spatial_history
-                            } else {
-                                0
-                            };
-                    }
-                    ev::KeyCode::Char('t') => {
-                        render.config.restir.max_temporal_history =
-                            if render.config.restir.max_temporal_history == 0 {
-                                temporal_history
-                            } else {
-                                0
-                            };
-                    }
-                    _ => {}
+                    // the played back session is not mixed with the user input
+                    _ if playback.is_some() => None,
+                    ev::KeyCode::Char(key) => Some(Action::Key(key)),
+                    ev::KeyCode::Left => Some(Action::MoveSun([-1, 0])),
+                    ev::KeyCode::Right => Some(Action::MoveSun([1, 0])),
+                    ev::KeyCode::Up => Some(Action::MoveSun([0, 1])),
+                    ev::KeyCode::Down => Some(Action::MoveSun([0, -1])),
+                    _ => None,
                 },
+                ev::Event::Mouse(_) if playback.is_some() => None,
                 ev::Event::Mouse(ev::MouseEvent {
                     kind,
                     column,
@@ -624,21 +2194,30 @@ fn main() {
                     modifiers: _,
                 }) => match kind {
                     ev::MouseEventKind::Down(ev::MouseButton::Left) => {
-                        sun_drag_start = Some(([column, row], render.config.world.sun_position));
+                        drag = render.pick(column, row);
+                        match drag {
+                            Some(_) => None,
+                            None => render.inspect_at(column, row),
+                        }
                     }
                     ev::MouseEventKind::Up(ev::MouseButton::Left) => {
-                        sun_drag_start = None;
+                        drag = None;
+                        None
                     }
-                    ev::MouseEventKind::Drag(ev::MouseButton::Left) => {
-                        if let Some((src_start_pos, dst_start_pos)) = sun_drag_start {
-                            render.config.world.sun_position = [
-                                dst_start_pos[0] + column - src_start_pos[0],
-                                dst_start_pos[1] + src_start_pos[1] - row,
-                            ];
-                        }
-                    }
-                    _ => {}
+                    ev::MouseEventKind::Drag(ev::MouseButton::Left) => match drag {
+                        Some(ref drag) => render.drag(drag, column, row),
+                        None => None,
+                    },
+                    _ => None,
                 },
+            };
+            if let Some(action) = action {
+                if let Some(ref mut recording) = recording {
+                    recording
+                        .record(render.tick, &action)
+                        .expect("Unable to record the session");
+                }
+                render.apply(&action);
             }
         }
     }
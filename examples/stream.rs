@@ -0,0 +1,189 @@
+/*!
+Weighted sampling of a data stream.
+
+Reads `key,weight` records from the standard input and maintains
+a sample of `K` records, with the probability of picking a record
+proportional to its weight, reporting the sample periodically.
+This is useful for log or telemetry sampling, where the stream
+is too large to be stored, or never ends.
+
+Two samplers are maintained side by side:
+    - `K` independent reservoirs from this crate, each holding one record,
+      so the sample is taken with replacement. The history is 64-bit,
+      and the weights are summed in `f64`, since the stream can be arbitrarily long
+    - the A-ExpJ algorithm by Efraimidis and Spirakis, which samples
+      without replacement and skips over records with exponential jumps,
+      consuming much fewer random numbers
+
+Usage: `cargo run --example stream -- [K] [report_interval] < records.csv`
+!*/
+
+use std::{
+    cmp::Ordering,
+    collections::BinaryHeap,
+    io::{self, BufRead as _},
+};
+
+/// Sample of `K` records with replacement, built from independent reservoirs.
+struct ReplacementSampler {
+    slots: Vec<rs_voir::LargeReservoirBuilder<Option<String>>>,
+}
+
+impl ReplacementSampler {
+    fn new(size: usize) -> Self {
+        Self {
+            slots: (0..size).map(|_| Default::default()).collect(),
+        }
+    }
+
+    fn add(&mut self, key: &str, weight: f64, random: &mut impl rand::Rng) {
+        for builder in self.slots.iter_mut() {
+            // all the records come from the stream with the same probability
+            builder.stream(1.0, weight, Some(key.to_string()), random);
+        }
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &str> {
+        self.slots
+            .iter()
+            .filter_map(|builder| builder.selected().as_deref())
+    }
+}
+
+struct KeyedRecord {
+    key: f64,
+    name: String,
+}
+
+impl PartialEq for KeyedRecord {
+    fn eq(&self, other: &Self) -> bool {
+        self.key == other.key
+    }
+}
+impl Eq for KeyedRecord {}
+impl PartialOrd for KeyedRecord {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+impl Ord for KeyedRecord {
+    fn cmp(&self, other: &Self) -> Ordering {
+        // reversed, so that the heap top is the smallest key
+        other.key.total_cmp(&self.key)
+    }
+}
+
+/// Sample of `K` records without replacement, using the A-ExpJ algorithm.
+struct ExpJumpSampler {
+    size: usize,
+    heap: BinaryHeap<KeyedRecord>,
+    /// Weight left to skip before the next insertion.
+    jump: f64,
+}
+
+impl ExpJumpSampler {
+    fn new(size: usize) -> Self {
+        Self {
+            size,
+            heap: BinaryHeap::with_capacity(size),
+            jump: 0.0,
+        }
+    }
+
+    fn reset_jump(&mut self, random: &mut impl rand::Rng) {
+        let threshold = self.heap.peek().unwrap().key;
+        self.jump = random.gen::<f64>().ln() / threshold.ln();
+    }
+
+    fn add(&mut self, name: &str, weight: f64, random: &mut impl rand::Rng) {
+        if self.heap.len() < self.size {
+            self.heap.push(KeyedRecord {
+                key: random.gen::<f64>().powf(1.0 / weight),
+                name: name.to_string(),
+            });
+            if self.heap.len() == self.size {
+                self.reset_jump(random);
+            }
+            return;
+        }
+
+        self.jump -= weight;
+        if self.jump <= 0.0 {
+            let threshold = self.heap.peek().unwrap().key;
+            let low = threshold.powf(weight);
+            let r = random.gen_range(low..1.0);
+            self.heap.pop();
+            self.heap.push(KeyedRecord {
+                key: r.powf(1.0 / weight),
+                name: name.to_string(),
+            });
+            self.reset_jump(random);
+        }
+    }
+
+    fn keys(&self) -> impl Iterator<Item = &str> {
+        self.heap.iter().map(|record| record.name.as_str())
+    }
+}
+
+fn report(count: usize, total_weight: f64, samplers: (&ReplacementSampler, &ExpJumpSampler)) {
+    println!("After {} records of total weight {}:", count, total_weight);
+    println!(
+        "\twith replacement: {}",
+        samplers.0.keys().collect::<Vec<_>>().join(" ")
+    );
+    println!(
+        "\twithout replacement: {}",
+        samplers.1.keys().collect::<Vec<_>>().join(" ")
+    );
+}
+
+fn main() {
+    let mut args = std::env::args().skip(1);
+    let size = args
+        .next()
+        .map_or(5, |arg| arg.parse::<usize>().expect("sample size"));
+    let report_interval = args
+        .next()
+        .map_or(1000, |arg| arg.parse::<usize>().expect("report interval"));
+
+    let mut random = rand::thread_rng();
+    let mut with_replacement = ReplacementSampler::new(size);
+    let mut without_replacement = ExpJumpSampler::new(size);
+    let mut count = 0;
+    let mut total_weight = 0.0;
+
+    for line in io::stdin().lock().lines() {
+        let line = line.unwrap();
+        let record = line
+            .split_once(',')
+            .and_then(|(key, weight)| Some((key.trim(), weight.trim().parse::<f64>().ok()?)));
+        let (key, weight) = match record {
+            Some((key, weight)) if weight > 0.0 && weight.is_finite() => (key, weight),
+            _ => {
+                eprintln!("Skipping record: {}", line);
+                continue;
+            }
+        };
+
+        with_replacement.add(key, weight, &mut random);
+        without_replacement.add(key, weight, &mut random);
+        count += 1;
+        total_weight += weight;
+        if count % report_interval == 0 {
+            report(
+                count,
+                total_weight,
+                (&with_replacement, &without_replacement),
+            );
+        }
+    }
+
+    if count % report_interval != 0 {
+        report(
+            count,
+            total_weight,
+            (&with_replacement, &without_replacement),
+        );
+    }
+}
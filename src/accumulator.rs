@@ -0,0 +1,166 @@
+//! Temporal accumulation of the rendered values.
+
+/// How the new values are blended with the accumulated ones.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum AccumulationMode {
+    /// Exponential moving average, with the given weight of a new value.
+    /// Adapts to the changes quickly, but never fully converges.
+    Exponential(f32),
+    /// True mean of all the values since the last reset.
+    Mean,
+}
+
+/// Accumulator of a value with `N` components, tracking its mean and variance.
+///
+/// The data is stored in f64, so that long accumulations don't lose precision.
+#[derive(Clone, Debug)]
+pub struct Accumulator<const N: usize> {
+    mode: AccumulationMode,
+    mean: [f64; N],
+    /// Variance in the exponential mode, or the sum of the squared deviations
+    /// from the mean in the mean mode, updated with Welford's algorithm.
+    deviation: f64,
+    count: u64,
+    version: u64,
+}
+
+impl<const N: usize> Default for Accumulator<N> {
+    fn default() -> Self {
+        Self::new(AccumulationMode::Mean)
+    }
+}
+
+impl<const N: usize> Accumulator<N> {
+    /// Create an empty accumulator.
+    pub fn new(mode: AccumulationMode) -> Self {
+        Self {
+            mode,
+            mean: [0.0; N],
+            deviation: 0.0,
+            count: 0,
+            version: 0,
+        }
+    }
+
+    /// Construct an accumulator from the parts returned by [`Accumulator::to_parts`],
+    /// e.g. when restoring it from a snapshot.
+    pub fn from_parts(
+        mode: AccumulationMode,
+        (mean, variance, count): ([f64; N], f64, u64),
+    ) -> Self {
+        let deviation = match mode {
+            AccumulationMode::Exponential(_) => variance,
+            AccumulationMode::Mean => variance * count.saturating_sub(1) as f64,
+        };
+        Self {
+            mode,
+            mean,
+            deviation,
+            count,
+            version: 0,
+        }
+    }
+
+    /// Return the accumulated mean, variance, and the number of values.
+    pub fn to_parts(&self) -> ([f64; N], f64, u64) {
+        (self.mean, self.variance_f64(), self.count)
+    }
+
+    /// Start accumulating from scratch.
+    pub fn reset(&mut self) {
+        *self = Self::new(self.mode);
+    }
+
+    /// Start accumulating from scratch if the given version of the inputs,
+    /// such as a counter of the scene changes, differs from the last seen one.
+    pub fn reset_on_change(&mut self, version: u64) {
+        if self.version != version {
+            self.reset();
+            self.version = version;
+        }
+    }
+
+    /// Add a new value.
+    pub fn add(&mut self, value: [f32; N]) {
+        self.count += 1;
+        match self.mode {
+            AccumulationMode::Exponential(weight) => {
+                let weight = weight as f64;
+                let mut deviation = 0.0;
+                for (mean, &value) in self.mean.iter_mut().zip(value.iter()) {
+                    let delta = value as f64 - *mean;
+                    deviation += delta * delta;
+                    *mean += weight * delta;
+                }
+                self.deviation += weight * (deviation - self.deviation);
+            }
+            AccumulationMode::Mean => {
+                let weight = 1.0 / self.count as f64;
+                for (mean, &value) in self.mean.iter_mut().zip(value.iter()) {
+                    let delta = value as f64 - *mean;
+                    *mean += weight * delta;
+                    self.deviation += delta * (value as f64 - *mean);
+                }
+            }
+        }
+    }
+
+    fn variance_f64(&self) -> f64 {
+        match self.mode {
+            AccumulationMode::Exponential(_) => self.deviation,
+            AccumulationMode::Mean if self.count < 2 => 0.0,
+            AccumulationMode::Mean => self.deviation / (self.count - 1) as f64,
+        }
+    }
+
+    /// Return the mode of accumulation.
+    pub fn mode(&self) -> AccumulationMode {
+        self.mode
+    }
+
+    /// Return the accumulated mean.
+    pub fn mean(&self) -> [f32; N] {
+        self.mean.map(|mean| mean as f32)
+    }
+
+    /// Return the accumulated variance, summed over the components.
+    /// In the mean mode, it's the sample variance, zero if there are less than two values.
+    pub fn variance(&self) -> f32 {
+        self.variance_f64() as f32
+    }
+
+    /// Return the number of values since the last reset.
+    pub fn count(&self) -> u64 {
+        self.count
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn mean_of_constant() {
+        let mut accumulator = Accumulator::<2>::new(AccumulationMode::Mean);
+        for _ in 0..10 {
+            accumulator.add([3.0, -1.0]);
+        }
+        assert_eq!(accumulator.mean(), [3.0, -1.0]);
+        assert_eq!(accumulator.variance(), 0.0);
+    }
+
+    #[test]
+    fn mean_variance() {
+        let mut accumulator = Accumulator::<1>::new(AccumulationMode::Mean);
+        for value in [1.0, 2.0, 3.0, 4.0] {
+            accumulator.add([value]);
+        }
+        assert_eq!(accumulator.mean(), [2.5]);
+        assert!((accumulator.variance() - 5.0 / 3.0).abs() < 1e-6);
+
+        let (mean, variance, count) = accumulator.to_parts();
+        let restored =
+            Accumulator::<1>::from_parts(AccumulationMode::Mean, (mean, variance, count));
+        assert_eq!(restored.variance(), accumulator.variance());
+    }
+}
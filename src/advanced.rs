@@ -0,0 +1,34 @@
+//! Raw access to the internals of the builder, for prototyping new algorithms,
+//! such as the MIS or normalization schemes that the crate doesn't provide.
+//!
+//! Nothing here keeps the reservoir consistent: changing the weights or the history
+//! by hand is a sure way to get a biased estimate, unless the math is worked out.
+//! The methods live in a trait that needs to be imported explicitly.
+
+use crate::{ReservoirBuilder, Scalar};
+
+/// Setters of the builder state, see the [module](self) docs.
+pub trait RawBuilder<F> {
+    /// Replace the sum of the resampling weights.
+    fn set_weight_sum(&mut self, weight_sum: F);
+    /// Replace the history, without touching the weights.
+    fn set_history(&mut self, history: u32);
+    /// Replace the target PDF of the selected sample.
+    fn set_selected_target_pdf(&mut self, target_pdf: F);
+}
+
+impl<S, F: Scalar> RawBuilder<F> for ReservoirBuilder<S, F> {
+    fn set_weight_sum(&mut self, weight_sum: F) {
+        self.weight_square_sum = self.scaled_square_sum(weight_sum, self.weight_sum());
+        self.weight_sum = weight_sum;
+        self.compensation = self.compensation.map(|_| F::ZERO);
+    }
+
+    fn set_history(&mut self, history: u32) {
+        self.history = history;
+    }
+
+    fn set_selected_target_pdf(&mut self, target_pdf: F) {
+        self.selected_target_pdf = target_pdf;
+    }
+}
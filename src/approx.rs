@@ -0,0 +1,56 @@
+//! Tolerance-aware comparison of the reservoirs, for testing.
+
+use crate::{Reservoir, ReservoirBuilder, Scalar};
+
+/// Check if two values are equal up to the relative tolerance.
+fn is_close<F: Scalar>(a: F, b: F, tolerance: F) -> bool {
+    let scale = if a.abs() > b.abs() { a.abs() } else { b.abs() };
+    (a - b).abs() <= tolerance * scale
+}
+
+impl<S, F: Scalar> Reservoir<S, F> {
+    /// Check if the weights of two reservoirs are equal up to the relative tolerance,
+    /// with the history and frames matching exactly. The samples aren't compared.
+    pub fn approx_eq<T>(&self, other: &Reservoir<T, F>, tolerance: F) -> bool {
+        self.history == other.history
+            && self.frames == other.frames
+            && is_close(
+                self.contribution_weight,
+                other.contribution_weight,
+                tolerance,
+            )
+            && is_close(self.target_pdf, other.target_pdf, tolerance)
+    }
+}
+
+impl<S, F: Scalar> ReservoirBuilder<S, F> {
+    /// Check if the weights of two builders are equal up to the relative tolerance,
+    /// with the history matching exactly. The samples aren't compared.
+    pub fn approx_eq<T>(&self, other: &ReservoirBuilder<T, F>, tolerance: F) -> bool {
+        self.history == other.history
+            && is_close(self.weight_sum(), other.weight_sum(), tolerance)
+            && is_close(
+                self.selected_target_pdf,
+                other.selected_target_pdf,
+                tolerance,
+            )
+    }
+}
+
+/// Assert that two reservoirs or builders are equal up to the relative tolerance,
+/// see [`Reservoir::approx_eq`] and [`ReservoirBuilder::approx_eq`].
+#[macro_export]
+macro_rules! assert_reservoir_close {
+    ($left:expr, $right:expr, $tolerance:expr $(,)?) => {
+        match (&$left, &$right) {
+            (left, right) => {
+                if !left.approx_eq(right, $tolerance) {
+                    panic!(
+                        "reservoirs are not close within {:?}:\n  left: {:?}\n right: {:?}",
+                        $tolerance, left, right
+                    );
+                }
+            }
+        }
+    };
+}
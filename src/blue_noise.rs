@@ -0,0 +1,96 @@
+//! Random streams reading from a tiled blue-noise mask.
+
+use crate::{random::pcg_hash, RandomSource};
+
+/// Golden ratio conjugate, as a fixed-point value.
+const GOLDEN_RATIO: u32 = 0x9e3779b9;
+
+/// Tiled blue-noise mask, e.g. loaded from a texture produced by the void-and-cluster method.
+///
+/// Driving the reservoir decisions of neighboring pixels with the mask makes the error
+/// spatially distributed as blue noise, which is perceptually pleasant and easier to denoise.
+#[derive(Clone, Copy, Debug)]
+pub struct BlueNoiseMask<'a> {
+    values: &'a [f32],
+    width: u32,
+    height: u32,
+}
+
+impl<'a> BlueNoiseMask<'a> {
+    /// Create a mask from the row-major values in [0, 1), with the given width.
+    pub fn new(values: &'a [f32], width: u32) -> Self {
+        assert_ne!(width, 0);
+        assert!(!values.is_empty(), "Empty mask");
+        assert_eq!(values.len() % width as usize, 0, "Incomplete mask rows");
+        Self {
+            values,
+            width,
+            height: (values.len() / width as usize) as u32,
+        }
+    }
+
+    /// Return the size of the tile, in pixels.
+    pub fn size(&self) -> [u32; 2] {
+        [self.width, self.height]
+    }
+
+    /// Create a stream for a pixel in a frame, starting at dimension 0.
+    pub fn stream(&self, pixel: [u32; 2], frame: u32) -> BlueNoiseStream<'a> {
+        BlueNoiseStream {
+            mask: *self,
+            pixel,
+            frame,
+            dimension: 0,
+        }
+    }
+}
+
+/// Stream of random numbers of a pixel, read from a [`BlueNoiseMask`].
+///
+/// Like with [`RandomStream`], every random decision consumes the next dimension,
+/// and passes can start at fixed dimensions. Every dimension reads the mask
+/// with a different toroidal offset, so the decisions aren't correlated,
+/// while every one of them stays blue noise across the pixels.
+/// Every frame adds a multiple of the golden ratio to the values,
+/// so that they are also well distributed over time.
+///
+/// [`RandomStream`]: crate::RandomStream
+#[derive(Clone, Debug)]
+pub struct BlueNoiseStream<'a> {
+    mask: BlueNoiseMask<'a>,
+    pixel: [u32; 2],
+    frame: u32,
+    dimension: u32,
+}
+
+impl BlueNoiseStream<'_> {
+    /// Return the dimension of the next random value.
+    pub fn dimension(&self) -> u32 {
+        self.dimension
+    }
+
+    /// Jump to the given dimension.
+    pub fn set_dimension(&mut self, dimension: u32) {
+        self.dimension = dimension;
+    }
+
+    /// Return the value of the given dimension, in [0, 1).
+    pub fn sample(&self, dimension: u32) -> f32 {
+        let hash = pcg_hash(dimension);
+        let x = (self.pixel[0] % self.mask.width + hash % self.mask.width) % self.mask.width;
+        let y =
+            (self.pixel[1] % self.mask.height + (hash >> 16) % self.mask.height) % self.mask.height;
+        let value = self.mask.values[(y * self.mask.width + x) as usize];
+        let fixed =
+            ((value * 4294967296.0) as u32).wrapping_add(self.frame.wrapping_mul(GOLDEN_RATIO));
+        (fixed >> 8) as f32 / 16777216.0
+    }
+}
+
+impl RandomSource for BlueNoiseStream<'_> {
+    fn uniform(&mut self) -> f32 {
+        let value = self.sample(self.dimension);
+        self.dimension += 1;
+        value
+    }
+}
@@ -0,0 +1,125 @@
+//! Scheduling of a fixed per-frame budget across the resampling stages.
+
+/// Stage of the pipeline that consumes rays.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stage {
+    /// Generating and evaluating the initial candidates.
+    Initial,
+    /// Tracing the shadow rays of the selected samples.
+    Visibility,
+    /// Re-validating the reused samples.
+    Validation,
+}
+
+/// Number of rays allocated to every stage for a frame.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub struct SampleBudget {
+    /// Rays for the initial candidates, in total.
+    pub initial: u32,
+    /// Shadow rays, in total.
+    pub visibility: u32,
+    /// Validation rays, in total.
+    pub validation: u32,
+}
+
+/// Scheduler splitting a per-frame budget between the stages,
+/// based on the measured cost of a ray in each of them.
+///
+/// Every selected sample needs a shadow ray, so the visibility stage gets
+/// one ray per pixel first. A fraction of what's left goes to validation,
+/// up to one ray per pixel, and the rest to the initial candidates,
+/// with at least one per pixel.
+/// The budget and the costs can be in any unit, e.g. milliseconds.
+#[derive(Clone, Debug)]
+pub struct BudgetScheduler {
+    frame_budget: f32,
+    validation_fraction: f32,
+    smoothing: f32,
+    costs: [f32; 3],
+}
+
+impl BudgetScheduler {
+    /// Create a scheduler with the given budget per frame,
+    /// assuming a unit cost for all the stages until they are measured.
+    pub fn new(frame_budget: f32) -> Self {
+        Self {
+            frame_budget,
+            validation_fraction: 0.25,
+            smoothing: 0.1,
+            costs: [1.0; 3],
+        }
+    }
+
+    /// Set the fraction of the budget left after visibility to spend on validation.
+    pub fn with_validation_fraction(self, fraction: f32) -> Self {
+        Self {
+            validation_fraction: fraction.clamp(0.0, 1.0),
+            ..self
+        }
+    }
+
+    /// Change the budget per frame.
+    pub fn set_frame_budget(&mut self, frame_budget: f32) {
+        self.frame_budget = frame_budget;
+    }
+
+    /// Report the measured cost of a stage, smoothing it over the frames.
+    pub fn report(&mut self, stage: Stage, rays: u32, elapsed: f32) {
+        if rays != 0 {
+            let cost = &mut self.costs[stage as usize];
+            *cost += self.smoothing * (elapsed / rays as f32 - *cost);
+        }
+    }
+
+    /// Return the estimated cost of a single ray in a stage.
+    pub fn cost(&self, stage: Stage) -> f32 {
+        self.costs[stage as usize]
+    }
+
+    /// Allocate the rays of the next frame.
+    pub fn schedule(&self, pixel_count: u32) -> SampleBudget {
+        let rays_for = |budget: f32, stage| (budget.max(0.0) / self.cost(stage)) as u32;
+        let visibility = pixel_count;
+        let remaining = self.frame_budget - visibility as f32 * self.cost(Stage::Visibility);
+        let validation =
+            rays_for(remaining * self.validation_fraction, Stage::Validation).min(pixel_count);
+        let initial = rays_for(
+            remaining - validation as f32 * self.cost(Stage::Validation),
+            Stage::Initial,
+        );
+        SampleBudget {
+            initial: initial.max(pixel_count),
+            visibility,
+            validation,
+        }
+    }
+}
+
+/// Distribute a number of rays between the pixels proportionally to their need,
+/// e.g. the variance, with at least `min_count` for each pixel.
+///
+/// The counts add up to the total, unless it's below the minimum.
+/// Pixels without any need are treated equally.
+pub fn distribute(total: u32, min_count: u32, need: &[f32], counts: &mut [u32]) {
+    assert_eq!(need.len(), counts.len());
+    let reserved = min_count as u64 * counts.len() as u64;
+    let extra = (total as u64).saturating_sub(reserved) as f64;
+    let need_sum = need.iter().map(|&n| n.max(0.0) as f64).sum::<f64>();
+    // round the cumulative shares, so that the rounding errors don't add up
+    let mut cumulative = 0.0;
+    let mut allocated = 0;
+    for (index, (count, &pixel_need)) in counts.iter_mut().zip(need).enumerate() {
+        cumulative += if need_sum > 0.0 {
+            pixel_need.max(0.0) as f64 / need_sum
+        } else {
+            1.0 / need.len() as f64
+        };
+        let end = if index + 1 == need.len() {
+            extra as u64
+        } else {
+            (extra * cumulative).round() as u64
+        };
+        *count = min_count + (end - allocated) as u32;
+        allocated = end;
+    }
+}
@@ -0,0 +1,89 @@
+//! Resampling several times from the same set of candidates.
+
+use crate::{RandomSource, Scalar};
+
+/// Set of candidates streamed once, and then resampled any number of times
+/// with replacement, proportionally to their resampling weights.
+///
+/// Each draw is a regular RIS sample with its own contribution weight,
+/// so the shading work can be split across several of them,
+/// averaging their estimates. The candidates are stored with the prefix sums
+/// of the weights, so every draw is a binary search.
+#[derive(Clone, Debug)]
+pub struct CandidateSet<S = (), F = f32> {
+    samples: Vec<S>,
+    target_values: Vec<F>,
+    prefix_sums: Vec<F>,
+    history: u32,
+}
+
+impl<S, F> Default for CandidateSet<S, F> {
+    fn default() -> Self {
+        Self {
+            samples: Vec::new(),
+            target_values: Vec::new(),
+            prefix_sums: Vec::new(),
+            history: 0,
+        }
+    }
+}
+
+impl<S, F: Scalar> CandidateSet<S, F> {
+    /// Stream in a new candidate, see [`ReservoirBuilder::stream`].
+    ///
+    /// [`ReservoirBuilder::stream`]: crate::ReservoirBuilder::stream
+    pub fn stream(&mut self, source_pdf: F, target_value: F, sample: S) {
+        let weight = target_value / source_pdf;
+        let weight_sum = self.weight_sum();
+        self.samples.push(sample);
+        self.target_values.push(target_value);
+        self.prefix_sums.push(weight_sum + weight);
+        self.history = self.history.saturating_add(1);
+    }
+
+    /// Register a candidate with zero value.
+    pub fn add_empty_sample(&mut self) {
+        self.history = self.history.saturating_add(1);
+    }
+
+    /// Return the number of candidates seen, including the empty ones.
+    pub fn history(&self) -> u32 {
+        self.history
+    }
+
+    /// Return the sum of the resampling weights.
+    pub fn weight_sum(&self) -> F {
+        self.prefix_sums.last().copied().unwrap_or(F::ZERO)
+    }
+
+    /// Return the sample of a streamed candidate, in the order of streaming.
+    pub fn sample(&self, index: usize) -> &S {
+        &self.samples[index]
+    }
+
+    /// Draw a candidate, returning its index and the contribution weight,
+    /// or `None` if none of the candidates has any weight.
+    pub fn draw<R: RandomSource>(&self, random: &mut R) -> Option<(usize, F)> {
+        let weight_sum = self.weight_sum();
+        if weight_sum <= F::ZERO {
+            return None;
+        }
+        let threshold = F::gen_unit(random) * weight_sum;
+        let index = self
+            .prefix_sums
+            .partition_point(|&sum| sum <= threshold)
+            // the rounding errors may push the threshold past the end
+            .min(self.prefix_sums.partition_point(|&sum| sum < weight_sum));
+        let denom = F::from_u32(self.history) * self.target_values[index];
+        Some((index, weight_sum / denom))
+    }
+
+    /// Draw `count` candidates independently, see [`CandidateSet::draw`].
+    pub fn draw_many<'a, R: RandomSource>(
+        &'a self,
+        count: usize,
+        random: &'a mut R,
+    ) -> impl Iterator<Item = (usize, F)> + 'a {
+        (0..count).filter_map(move |_| self.draw(random))
+    }
+}
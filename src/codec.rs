@@ -0,0 +1,298 @@
+//! Compact encodings of the reservoir data, for storing it in
+//! bandwidth-limited buffers or sending it over the network.
+//!
+//! The encoders implement [`Encoder`], and can be combined with [`Pair`]
+//! in order to assemble a packed format of a sample, for example:
+//! ```
+//! use rs_voir::codec::{Encoder as _, LogDistance, Normalized, Octahedral, Pair};
+//! let format = Pair(
+//!     Octahedral::<8>,
+//!     Pair(LogDistance::<10>::new(0.01..1000.0), Normalized::<6>::new(10.0)),
+//! );
+//! let bits = format.encode(([0.0, 0.6, 0.8], (5.0, 2.5)));
+//! let (dir, (distance, target_pdf)) = format.decode(bits);
+//! ```
+
+use std::ops::Range;
+
+/// Encoder of a value into a fixed number of bits.
+pub trait Encoder {
+    /// Type of the encoded value.
+    type Value;
+    /// Number of bits used by the encoding.
+    const BITS: u32;
+    /// Encode a value into the lower bits.
+    fn encode(&self, value: Self::Value) -> u32;
+    /// Decode a value from the lower bits, ignoring the upper ones.
+    fn decode(&self, bits: u32) -> Self::Value;
+}
+
+fn quantize(value: f32, bits: u32) -> u32 {
+    let max = ((1u64 << bits) - 1) as f32;
+    (value.clamp(0.0, 1.0) * max).round() as u32
+}
+
+fn dequantize(code: u32, bits: u32) -> f32 {
+    let max = (1u64 << bits) - 1;
+    (code as u64 & max) as f32 / max as f32
+}
+
+/// Combination of two encoders, with the first one taking the lower bits.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Pair<A, B>(pub A, pub B);
+
+impl<A: Encoder, B: Encoder> Pair<A, B> {
+    /// Rejects the pairs that don't fit into 32 bits at compile time.
+    const FITS: () = assert!(A::BITS + B::BITS <= 32, "Too many bits in the pair");
+}
+
+impl<A: Encoder, B: Encoder> Encoder for Pair<A, B> {
+    type Value = (A::Value, B::Value);
+    const BITS: u32 = A::BITS + B::BITS;
+    fn encode(&self, value: Self::Value) -> u32 {
+        let () = Self::FITS;
+        let high = self.1.encode(value.1).checked_shl(A::BITS).unwrap_or(0);
+        self.0.encode(value.0) | high
+    }
+    fn decode(&self, bits: u32) -> Self::Value {
+        let low_mask = ((1u64 << A::BITS) - 1) as u32;
+        (
+            self.0.decode(bits & low_mask),
+            self.1.decode(bits.checked_shr(A::BITS).unwrap_or(0)),
+        )
+    }
+}
+
+/// Unit vector, mapped onto an octahedron, with `B` bits per coordinate.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct Octahedral<const B: u32>;
+
+impl<const B: u32> Encoder for Octahedral<B> {
+    type Value = [f32; 3];
+    const BITS: u32 = 2 * B;
+    fn encode(&self, [x, y, z]: [f32; 3]) -> u32 {
+        let norm = x.abs() + y.abs() + z.abs();
+        let (mut u, mut v) = (x / norm, y / norm);
+        if z < 0.0 {
+            // fold the lower hemisphere over the diagonals
+            (u, v) = ((1.0 - v.abs()).copysign(u), (1.0 - u.abs()).copysign(v));
+        }
+        quantize(u * 0.5 + 0.5, B) | (quantize(v * 0.5 + 0.5, B) << B)
+    }
+    fn decode(&self, bits: u32) -> [f32; 3] {
+        let u = dequantize(bits, B) * 2.0 - 1.0;
+        let v = dequantize(bits >> B, B) * 2.0 - 1.0;
+        let z = 1.0 - u.abs() - v.abs();
+        let (x, y) = if z < 0.0 {
+            ((1.0 - v.abs()).copysign(u), (1.0 - u.abs()).copysign(v))
+        } else {
+            (u, v)
+        };
+        let length = (x * x + y * y + z * z).sqrt();
+        [x / length, y / length, z / length]
+    }
+}
+
+/// Positive distance within a range, quantized logarithmically with `B` bits,
+/// so that the relative error is the same across the range.
+#[derive(Clone, Debug)]
+pub struct LogDistance<const B: u32> {
+    log_min: f32,
+    log_max: f32,
+}
+
+impl<const B: u32> LogDistance<B> {
+    /// Create an encoder for the given range of distances.
+    /// Distances outside of it are clamped.
+    pub fn new(range: Range<f32>) -> Self {
+        Self {
+            log_min: range.start.ln(),
+            log_max: range.end.ln(),
+        }
+    }
+}
+
+impl<const B: u32> Encoder for LogDistance<B> {
+    type Value = f32;
+    const BITS: u32 = B;
+    fn encode(&self, distance: f32) -> u32 {
+        quantize(
+            (distance.ln() - self.log_min) / (self.log_max - self.log_min),
+            B,
+        )
+    }
+    fn decode(&self, bits: u32) -> f32 {
+        (self.log_min + dequantize(bits, B) * (self.log_max - self.log_min)).exp()
+    }
+}
+
+/// Non-negative value normalized by the given maximum, such as the target PDF
+/// relative to the brightest light, quantized linearly with `B` bits.
+#[derive(Clone, Debug)]
+pub struct Normalized<const B: u32> {
+    max: f32,
+}
+
+impl<const B: u32> Normalized<B> {
+    /// Create an encoder for the values up to the given maximum.
+    /// Values above it are clamped.
+    pub fn new(max: f32) -> Self {
+        Self { max }
+    }
+}
+
+impl<const B: u32> Encoder for Normalized<B> {
+    type Value = f32;
+    const BITS: u32 = B;
+    fn encode(&self, value: f32) -> u32 {
+        quantize(value / self.max, B)
+    }
+    fn decode(&self, bits: u32) -> f32 {
+        dequantize(bits, B) * self.max
+    }
+}
+
+/// Three non-negative values packed into 32 bits as small floats,
+/// similar to the R11G11B10 format of the GPUs, but with a wider exponent range.
+///
+/// Every value has its own 6-bit exponent, so values of very different magnitude,
+/// like the weight sum, contribution weight, and the target PDF, keep their precision.
+/// The first two values get a 5-bit mantissa, and the last one a 4-bit mantissa,
+/// bounding the relative error by 2^-6 and 2^-5 respectively.
+/// This holds within [2^-30, 2^32], while the smaller values are stored as zero,
+/// and the larger ones are clamped. Negative values and NaN are stored as zero.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub struct PackedFloats(pub u32);
+
+impl PackedFloats {
+    const EXPONENT_BITS: u32 = 6;
+    const MANTISSA_BITS: [u32; 3] = [5, 5, 4];
+    /// Difference between the exponent biases of `f32` and the packed floats.
+    const BIAS_SHIFT: i32 = 127 - 31;
+
+    fn encode_value(value: f32, mantissa_bits: u32) -> u32 {
+        let field_bits = Self::EXPONENT_BITS + mantissa_bits;
+        let max_code = (1 << field_bits) - 1;
+        if value.is_nan() || value <= 0.0 {
+            return 0;
+        }
+        if value.is_infinite() {
+            return max_code;
+        }
+        // round to nearest, carrying the overflow of the mantissa into the exponent
+        let drop = 23 - mantissa_bits;
+        let rounded = (value.to_bits() + (1 << (drop - 1))) >> drop;
+        let exponent = (rounded >> mantissa_bits) as i32 - Self::BIAS_SHIFT;
+        if exponent <= 0 {
+            0
+        } else if exponent >= 1 << Self::EXPONENT_BITS {
+            max_code
+        } else {
+            ((exponent as u32) << mantissa_bits) | (rounded & ((1 << mantissa_bits) - 1))
+        }
+    }
+
+    fn decode_value(code: u32, mantissa_bits: u32) -> f32 {
+        let exponent = code >> mantissa_bits;
+        if exponent == 0 {
+            return 0.0;
+        }
+        let mantissa = code & ((1 << mantissa_bits) - 1);
+        let biased_exponent = (exponent as i32 + Self::BIAS_SHIFT) as u32;
+        f32::from_bits((biased_exponent << 23) | (mantissa << (23 - mantissa_bits)))
+    }
+
+    /// Encode the values.
+    pub fn encode(values: [f32; 3]) -> Self {
+        let mut bits = 0;
+        let mut offset = 0;
+        for (&value, &mantissa_bits) in values.iter().zip(Self::MANTISSA_BITS.iter()) {
+            bits |= Self::encode_value(value, mantissa_bits) << offset;
+            offset += Self::EXPONENT_BITS + mantissa_bits;
+        }
+        Self(bits)
+    }
+
+    /// Decode the values.
+    pub fn decode(self) -> [f32; 3] {
+        let mut offset = 0;
+        Self::MANTISSA_BITS.map(|mantissa_bits| {
+            let field_bits = Self::EXPONENT_BITS + mantissa_bits;
+            let code = (self.0 >> offset) & ((1 << field_bits) - 1);
+            offset += field_bits;
+            Self::decode_value(code, mantissa_bits)
+        })
+    }
+}
+
+impl Encoder for PackedFloats {
+    type Value = [f32; 3];
+    const BITS: u32 = 32;
+    fn encode(&self, values: [f32; 3]) -> u32 {
+        Self::encode(values).0
+    }
+    fn decode(&self, bits: u32) -> [f32; 3] {
+        Self(bits).decode()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_relative_error(value: f32, decoded: f32, bound: f32) {
+        let error = (decoded - value).abs() / value;
+        assert!(error <= bound, "{} decoded as {}", value, decoded);
+    }
+
+    #[test]
+    fn packed_floats_round_trip() {
+        let mut value = 2f32.powi(-30);
+        while value < 2f32.powi(32) {
+            let values = [value, value * 1.37, value * 0.71];
+            let decoded = PackedFloats::encode(values).decode();
+            for i in 0..3 {
+                let bound = if i < 2 { 2f32.powi(-6) } else { 2f32.powi(-5) };
+                if values[i] >= 2f32.powi(-30) && values[i] < 2f32.powi(32) {
+                    assert_relative_error(values[i], decoded[i], bound);
+                }
+            }
+            value *= 1.09;
+        }
+    }
+
+    #[test]
+    fn packed_floats_different_magnitudes() {
+        let values = [40.0, 0.5, 0.02];
+        let decoded = PackedFloats::encode(values).decode();
+        for (value, decoded) in values.into_iter().zip(decoded) {
+            assert_relative_error(value, decoded, 2f32.powi(-5));
+        }
+    }
+
+    #[test]
+    fn packed_floats_out_of_range() {
+        let decoded = PackedFloats::encode([-1.0, f32::NAN, 1e-12]).decode();
+        assert_eq!(decoded, [0.0; 3]);
+        let decoded = PackedFloats::encode([f32::INFINITY, 1e20, 0.0]).decode();
+        assert!(decoded[0] > 4e9 && decoded[0] == decoded[1]);
+        assert_eq!(decoded[2], 0.0);
+    }
+
+    #[test]
+    fn pair_with_full_first_part() {
+        let codec = Pair(PackedFloats::default(), Normalized::<0>::new(1.0));
+        let values = [40.0, 0.5, 0.02];
+        let bits = codec.encode((values, 0.0));
+        assert_eq!(bits, PackedFloats::encode(values).0);
+        assert_eq!(codec.decode(bits).0, PackedFloats::encode(values).decode());
+    }
+
+    #[test]
+    fn log_distance_round_trip() {
+        let codec = LogDistance::<16>::new(0.01..1000.0);
+        for &distance in &[0.01, 0.5, 3.0, 999.0] {
+            assert_relative_error(distance, codec.decode(codec.encode(distance)), 1e-3);
+        }
+    }
+}
@@ -0,0 +1,104 @@
+//! Reservoirs of samples carrying an RGB contribution.
+
+use crate::{target, RandomSource, Reservoir, ReservoirBuilder, Scalar};
+
+/// Reduction of an RGB color into a scalar target value, see the [`target`] module.
+pub type Reduction = fn([f32; 3]) -> f32;
+
+/// Compute the target value of a color, making sure it's positive
+/// whenever the color isn't black.
+///
+/// A reduction can be zero for a color that still contributes, e.g. a luminance
+/// with a negative component. Such samples could never be selected, making the
+/// estimate biased, so they fall back to a small fraction of the largest component.
+fn target_value(reduction: Reduction, color: [f32; 3]) -> f32 {
+    let value = reduction(color);
+    if value > 0.0 {
+        value
+    } else {
+        target::max_component(color.map(f32::abs)) * 1e-3
+    }
+}
+
+/// Builder for a reservoir of RGB samples.
+///
+/// Resampling is driven by a scalar reduction of the color,
+/// while the full color is kept for the shading.
+/// The colors are always `f32`, while the weights are in the scalar type `F`.
+#[derive(Clone, Debug)]
+pub struct ColorReservoirBuilder<F = f32> {
+    builder: ReservoirBuilder<[f32; 3], F>,
+    reduction: Reduction,
+}
+
+/// A ready to use reservoir of RGB samples.
+#[derive(Clone, Debug, Default)]
+pub struct ColorReservoir<F = f32> {
+    /// Reservoir of the selected sample.
+    pub reservoir: Reservoir<(), F>,
+    /// Color of the selected sample.
+    pub color: [f32; 3],
+}
+
+impl<F: Scalar> Default for ColorReservoirBuilder<F> {
+    fn default() -> Self {
+        Self::new(target::luminance)
+    }
+}
+
+impl<F: Scalar> ColorReservoirBuilder<F> {
+    /// Create an empty builder with the given reduction for the target value.
+    pub fn new(reduction: Reduction) -> Self {
+        Self {
+            builder: ReservoirBuilder::default(),
+            reduction,
+        }
+    }
+
+    /// Stream in a new sample with the given color.
+    ///
+    /// Returns true if the sample got stored into the reservoir.
+    pub fn stream<R: RandomSource>(
+        &mut self,
+        source_pdf: F,
+        color: [f32; 3],
+        random: &mut R,
+    ) -> bool {
+        let target_value = F::from_f32(target_value(self.reduction, color));
+        if target_value <= F::ZERO {
+            self.builder.add_empty_sample();
+            return false;
+        }
+        self.builder.stream(source_pdf, target_value, color, random)
+    }
+
+    /// Merge another reservoir into this one.
+    ///
+    /// Returns true if the other's sample got stored into the reservoir.
+    pub fn merge<R: RandomSource>(&mut self, other: &ColorReservoir<F>, random: &mut R) -> bool {
+        let target_value = F::from_f32(target_value(self.reduction, other.color));
+        if !other.reservoir.has_weight() || target_value <= F::ZERO {
+            self.builder.merge_history(&other.reservoir);
+            return false;
+        }
+        let other = other.reservoir.with_sample(other.color);
+        self.builder.merge(&other.to_builder(target_value), random)
+    }
+
+    /// Finish building a reservoir.
+    pub fn finish(self) -> ColorReservoir<F> {
+        let reservoir = self.builder.finish();
+        ColorReservoir {
+            color: *reservoir.sample(),
+            reservoir: reservoir.with_sample(()),
+        }
+    }
+}
+
+impl<F: Scalar> ColorReservoir<F> {
+    /// Return the color of the selected sample, weighted by its contribution weight.
+    pub fn contribution(&self) -> [f32; 3] {
+        let weight = self.reservoir.contribution_weight().to_f32();
+        self.color.map(|c| c * weight)
+    }
+}
@@ -0,0 +1,88 @@
+//! Adapter of the reservoir selection to the `rand` distributions.
+
+use crate::ReservoirBuilder;
+use rand::{
+    distributions::{Distribution, WeightedError},
+    Rng,
+};
+
+/// Distribution of items with the probability proportional to their weights,
+/// sampled by streaming all of them through a reservoir.
+///
+/// Unlike `WeightedIndex`, there is no cumulative table to rebuild,
+/// so items can be added at any time, and distributions can be merged.
+/// In exchange, every sample costs a pass over the items.
+#[derive(Clone, Debug)]
+pub struct WeightedReservoirDistribution<T> {
+    items: Vec<(T, f32)>,
+    total_weight: f32,
+}
+
+impl WeightedReservoirDistribution<usize> {
+    /// Create a distribution of the indices of the weights,
+    /// as a replacement for `WeightedIndex::new`.
+    pub fn from_weights(weights: impl IntoIterator<Item = f32>) -> Result<Self, WeightedError> {
+        Self::new(weights.into_iter().enumerate())
+    }
+}
+
+impl<T> WeightedReservoirDistribution<T> {
+    /// Create a distribution of the given items with their weights.
+    pub fn new(items: impl IntoIterator<Item = (T, f32)>) -> Result<Self, WeightedError> {
+        let mut distribution = Self {
+            items: Vec::new(),
+            total_weight: 0.0,
+        };
+        for (item, weight) in items {
+            distribution.push(item, weight)?;
+        }
+        if distribution.items.is_empty() {
+            Err(WeightedError::NoItem)
+        } else if distribution.total_weight <= 0.0 {
+            Err(WeightedError::AllWeightsZero)
+        } else {
+            Ok(distribution)
+        }
+    }
+
+    /// Add an item with the given weight.
+    pub fn push(&mut self, item: T, weight: f32) -> Result<(), WeightedError> {
+        if !(weight >= 0.0 && weight.is_finite()) {
+            return Err(WeightedError::InvalidWeight);
+        }
+        if self.items.len() >= u32::MAX as usize {
+            return Err(WeightedError::TooMany);
+        }
+        self.items.push((item, weight));
+        self.total_weight += weight;
+        Ok(())
+    }
+
+    /// Merge the items of another distribution into this one.
+    pub fn merge(&mut self, other: Self) {
+        self.items.extend(other.items);
+        self.total_weight += other.total_weight;
+    }
+
+    /// Return the sum of all the weights.
+    pub fn total_weight(&self) -> f32 {
+        self.total_weight
+    }
+
+    /// Select an item, returning its index.
+    fn select<R: Rng + ?Sized>(&self, rng: &mut R) -> usize {
+        let mut builder = ReservoirBuilder::default();
+        for (index, &(_, weight)) in self.items.iter().enumerate() {
+            if weight > 0.0 {
+                builder.stream_with_u(1.0, weight, index, rng.gen());
+            }
+        }
+        *builder.selected()
+    }
+}
+
+impl<T: Clone> Distribution<T> for WeightedReservoirDistribution<T> {
+    fn sample<R: Rng + ?Sized>(&self, rng: &mut R) -> T {
+        self.items[self.select(rng)].0.clone()
+    }
+}
@@ -0,0 +1,42 @@
+//! Errors of the validated reservoir operations.
+
+use std::fmt;
+
+/// Invalid input of [`ReservoirBuilder::try_stream`] or [`ReservoirBuilder::try_merge`].
+///
+/// Each variant carries the offending value.
+///
+/// [`ReservoirBuilder::try_stream`]: crate::ReservoirBuilder::try_stream
+/// [`ReservoirBuilder::try_merge`]: crate::ReservoirBuilder::try_merge
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum InputError<F = f32> {
+    /// Source PDF is not positive and finite.
+    SourcePdf(F),
+    /// Target value is negative or not finite.
+    TargetValue(F),
+    /// Weight sum of the merged reservoir is negative or not finite.
+    WeightSum(F),
+    /// Target PDF of the merged reservoir is negative or not finite.
+    TargetPdf(F),
+}
+
+impl<F: fmt::Debug> fmt::Display for InputError<F> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Self::SourcePdf(ref value) => {
+                write!(f, "source PDF {:?} is not positive and finite", value)
+            }
+            Self::TargetValue(ref value) => {
+                write!(f, "target value {:?} is negative or not finite", value)
+            }
+            Self::WeightSum(ref value) => {
+                write!(f, "weight sum {:?} is negative or not finite", value)
+            }
+            Self::TargetPdf(ref value) => {
+                write!(f, "target PDF {:?} is negative or not finite", value)
+            }
+        }
+    }
+}
+
+impl<F: fmt::Debug> std::error::Error for InputError<F> {}
@@ -0,0 +1,67 @@
+//! Subpixel jitter of the camera, and reprojection of the reservoirs under it.
+//!
+//! With TAA-style jitter, the G-buffer of every frame is rendered at a slightly
+//! different subpixel offset, while the reservoirs are stored per pixel.
+//! The reservoir of a previous pixel describes the point at its center
+//! shifted by the jitter of that frame, which the reprojection has to account for.
+
+/// Element of the Halton low-discrepancy sequence for the given prime base.
+fn halton(mut index: u32, base: u32) -> f32 {
+    let mut result = 0.0;
+    let mut fraction = 1.0;
+    while index > 0 {
+        fraction /= base as f32;
+        result += fraction * (index % base) as f32;
+        index /= base;
+    }
+    result
+}
+
+/// Repeating sequence of subpixel offsets, based on the Halton (2, 3) sequence.
+#[derive(Clone, Copy, Debug)]
+pub struct JitterSequence {
+    length: u32,
+}
+
+impl Default for JitterSequence {
+    fn default() -> Self {
+        Self::new(8)
+    }
+}
+
+impl JitterSequence {
+    /// Create a sequence repeating after the given number of frames.
+    pub fn new(length: u32) -> Self {
+        assert_ne!(length, 0);
+        Self { length }
+    }
+
+    /// Return the offset of the given frame, in pixels, within [-0.5, 0.5).
+    pub fn offset(&self, frame_index: u32) -> [f32; 2] {
+        // skip the zero element, which would always be in the corner
+        let index = frame_index % self.length + 1;
+        [halton(index, 2) - 0.5, halton(index, 3) - 0.5]
+    }
+}
+
+/// Return the jittered sample point of a pixel, in pixel units.
+pub fn sample_position(pixel: [i32; 2], jitter: [f32; 2]) -> [f32; 2] {
+    [0, 1].map(|axis| pixel[axis] as f32 + 0.5 + jitter[axis])
+}
+
+/// Pick the pixel of the previous frame to reuse the reservoir from.
+///
+/// The `position` is where the current [`sample_position`] lands in the previous frame,
+/// in pixel units, and `prev_jitter` is the offset of the previous frame.
+/// The reservoirs are not interpolated, so one of the four nearest pixels is picked
+/// stochastically with the bilinear weights, using the random values in `u`.
+/// The result may be outside of the screen.
+pub fn reprojection_pixel(position: [f32; 2], prev_jitter: [f32; 2], u: [f32; 2]) -> [i32; 2] {
+    [0, 1].map(|axis| {
+        // samples of the previous frame were at the pixel centers shifted by the jitter
+        let coordinate = position[axis] - prev_jitter[axis] - 0.5;
+        let base = coordinate.floor();
+        let offset = (u[axis] < coordinate - base) as i32;
+        base as i32 + offset
+    })
+}
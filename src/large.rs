@@ -0,0 +1,112 @@
+//! Reservoirs for very long streams.
+
+use crate::{RandomSource, Reservoir, Scalar};
+
+/// Builder for a reservoir with a 64-bit history, for sampling from streams
+/// of billions of records, which would overflow the history of [`ReservoirBuilder`].
+///
+/// The weights are `f64` by default, since `f32` can't keep track of the sum
+/// of that many weights anyway.
+///
+/// [`ReservoirBuilder`]: crate::ReservoirBuilder
+#[derive(Clone, Debug, Default)]
+pub struct LargeReservoirBuilder<S = (), F = f64> {
+    history: u64,
+    weight_sum: F,
+    selected: S,
+    selected_target_pdf: F,
+}
+
+impl<S, F: Scalar> LargeReservoirBuilder<S, F> {
+    /// Stream in a new sample, see [`ReservoirBuilder::stream`].
+    ///
+    /// Returns true if the sample got stored into the reservoir.
+    /// A sample with zero target value is only counted in the history,
+    /// without drawing a random number.
+    ///
+    /// [`ReservoirBuilder::stream`]: crate::ReservoirBuilder::stream
+    pub fn stream<R: RandomSource>(
+        &mut self,
+        source_pdf: F,
+        target_value: F,
+        sample: S,
+        random: &mut R,
+    ) -> bool {
+        if target_value == F::ZERO {
+            self.add_empty_sample();
+            return false;
+        }
+        let weight = target_value / source_pdf;
+        self.history += 1;
+        self.weight_sum = self.weight_sum + weight;
+        let stored = F::gen_unit(random) * self.weight_sum < weight;
+        if stored {
+            self.selected = sample;
+            self.selected_target_pdf = target_value;
+        }
+        stored
+    }
+
+    /// Register a sample with zero value.
+    pub fn add_empty_sample(&mut self) {
+        self.history += 1;
+    }
+
+    /// Return the stored history, in samples.
+    pub fn history(&self) -> u64 {
+        self.history
+    }
+
+    /// Return the sum of the resampling weights seen so far.
+    pub fn weight_sum(&self) -> F {
+        self.weight_sum
+    }
+
+    /// Return the selected sample.
+    pub fn selected(&self) -> &S {
+        &self.selected
+    }
+
+    /// Return the contribution weight of the selected sample.
+    pub fn contribution_weight(&self) -> F {
+        let denom = F::from_u64(self.history) * self.selected_target_pdf;
+        if denom > F::ZERO {
+            self.weight_sum / denom
+        } else {
+            F::ZERO
+        }
+    }
+
+    /// Finish building a regular reservoir.
+    ///
+    /// The contribution weight accounts for the full history,
+    /// while the stored history saturates at `u32::MAX`.
+    pub fn finish(self) -> Reservoir<S, F> {
+        let contribution_weight = self.contribution_weight();
+        let history = u32::try_from(self.history).unwrap_or(u32::MAX);
+        Reservoir {
+            history,
+            frames: history.min(1),
+            contribution_weight,
+            target_pdf: self.selected_target_pdf,
+            age: 0,
+            sample: self.selected,
+        }
+    }
+}
+
+impl<S: Clone, F: Scalar> LargeReservoirBuilder<S, F> {
+    /// Merge another builder into this one, e.g. from another thread.
+    ///
+    /// Returns true if the other's sample got stored into the reservoir.
+    pub fn merge<R: RandomSource>(&mut self, other: &Self, random: &mut R) -> bool {
+        self.history += other.history;
+        self.weight_sum = self.weight_sum + other.weight_sum;
+        let stored = F::gen_unit(random) * self.weight_sum < other.weight_sum;
+        if stored {
+            self.selected = other.selected.clone();
+            self.selected_target_pdf = other.selected_target_pdf;
+        }
+        stored
+    }
+}
@@ -1,157 +1,1669 @@
 #![warn(missing_docs)]
 
 //! Basic implementation of a Reservoir.
+//!
+//! The reservoirs, their builders, and the fixed-size containers of them never allocate,
+//! so they can be used in the allocation-restricted render threads.
+//! Only the types storing a variable number of candidates, like [`CandidateSet`],
+//! use the heap.
 
-use rand::Rng;
+mod accumulator;
+pub mod advanced;
+mod approx;
+mod blue_noise;
+pub mod budget;
+mod candidates;
+pub mod codec;
+mod color;
+#[cfg(feature = "rand")]
+mod distribution;
+mod error;
+pub mod jitter;
+mod large;
+mod mis;
+mod multi;
+mod observe;
+mod precision;
+mod quasi;
+mod random;
+#[cfg(feature = "rand")]
+pub mod sampling;
+mod scalar;
+pub mod shift;
+mod stats;
+pub mod target;
+
+pub use accumulator::{AccumulationMode, Accumulator};
+pub use blue_noise::{BlueNoiseMask, BlueNoiseStream};
+pub use candidates::CandidateSet;
+pub use color::{ColorReservoir, ColorReservoirBuilder, Reduction};
+#[cfg(feature = "rand")]
+pub use distribution::WeightedReservoirDistribution;
+pub use error::InputError;
+pub use large::LargeReservoirBuilder;
+pub use mis::PairwiseMis;
+pub use multi::{
+    MultiReservoirBuilder, PriorityReservoirBuilder, ReservoirArray, StratifiedReservoirBuilder,
+};
+pub use observe::{Observed, SelectionChange};
+pub use precision::Precision;
+pub use quasi::{QuasiRandomStream, QuasiSequence};
+pub use random::{RandomSource, RandomStream};
+pub use scalar::Scalar;
+pub use stats::{TargetStats, WithStats};
 
 /// Builder for a reservoir. Can stream in new samples and merge
 /// with other reservoirs.
+///
+/// The payload `S` describes the sample, e.g. the light index and the direction.
+/// It's stored together with the weights, and replaced whenever a new sample
+/// gets selected. The default `()` payload leaves it up to the caller.
+///
+/// The weights are stored as [`Scalar`] `F`, which is `f32` by default.
 #[derive(Clone, Default, Debug)]
-pub struct ReservoirBuilder {
+pub struct ReservoirBuilder<S = (), F = f32> {
     history: u32,
-    weight_sum: f32,
-    selected_target_pdf: f32,
+    weight_sum: F,
+    selected: S,
+    selected_target_pdf: F,
+    selected_age: u32,
+    merged_frames: u32,
+    has_new_samples: bool,
+    precision: Precision,
+    /// Running compensation of the weight sum, if enabled.
+    compensation: Option<F>,
+    /// Sum of the squared resampling weights, for the effective sample size.
+    weight_square_sum: F,
+    /// Number of samples registered with zero value.
+    empty_samples: u32,
+    /// A sample has been selected at some point.
+    has_selected: bool,
 }
 
 /// A ready to use reservoir.
+///
+/// The confidence of a reservoir is tracked in two ways:
+///   - history is the number of samples seen, used for weight normalization
+///   - frames is the number of frames worth of samples, useful for capping
+///     the temporal reuse independently of the number of samples per frame
+///
+/// Merging adds up the frames of both sides, so a spatial merge counts
+/// the frames worth of the neighbors, and capping the history scales
+/// the frames proportionally, keeping them bounded.
 #[derive(Clone, Default, Debug)]
-pub struct Reservoir {
+pub struct Reservoir<S = (), F = f32> {
     history: u32,
-    contribution_weight: f32,
+    frames: u32,
+    contribution_weight: F,
+    target_pdf: F,
+    age: u32,
+    sample: S,
+}
+
+/// Result of [`ReservoirBuilder::finish_detailed`], with the diagnostics
+/// that are otherwise lost when finishing, useful for tracking down bias.
+#[derive(Clone, Default, Debug)]
+pub struct FinishOutput<S = (), F = f32> {
+    /// The finished reservoir.
+    pub reservoir: Reservoir<S, F>,
+    /// Denominator of the contribution weight, i.e. the normalization
+    /// times the target PDF of the selected sample.
+    pub denominator: F,
+    /// Any sample has been selected by the builder, even if it got invalidated later.
+    pub has_selected: bool,
+    /// Number of samples registered with zero value, as a part of the history.
+    pub empty_samples: u32,
+}
+
+/// Neighbor reservoir to be merged with [`ReservoirBuilder::merge_neighbors`].
+#[derive(Clone, Default, Debug)]
+pub struct NeighborInput<S = (), F = f32> {
+    /// Reservoir of the neighbor, with the history already clamped,
+    /// and optionally scaled with [`Reservoir::with_similarity`].
+    /// The sample is expected to be shifted into the current domain.
+    pub reservoir: Reservoir<S, F>,
+    /// Target PDF of the neighbor's sample, shifted into the current domain.
+    /// Zero if the sample isn't valid there.
+    pub target_pdf: F,
+}
+
+/// Caps applied to a reservoir, independently limiting the history
+/// and the magnitude of the contribution weight.
+///
+/// Useful when combining reservoirs of very different quality.
+/// The default policy doesn't cap anything.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct CapPolicy<F = f32> {
+    /// Maximum history, see [`Reservoir::with_max_history`].
+    pub max_history: u32,
+    /// Maximum contribution weight, see [`Reservoir::with_max_contribution`].
+    pub max_contribution: F,
+}
+
+impl<F: Scalar> Default for CapPolicy<F> {
+    fn default() -> Self {
+        Self {
+            max_history: u32::MAX,
+            max_contribution: F::INFINITY,
+        }
+    }
+}
+
+/// Candidate sample for [`ReservoirBuilder::stream_candidate`]
+/// and [`ReservoirBuilder::stream_iter`].
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Candidate<S = (), F = f32> {
+    /// PDF of how the sample was produced.
+    pub source_pdf: F,
+    /// Value of the target function for the sample.
+    pub target_value: F,
+    /// Payload of the sample.
+    pub sample: S,
+}
+
+impl<F> From<(F, F)> for Candidate<(), F> {
+    fn from((source_pdf, target_value): (F, F)) -> Self {
+        Self {
+            source_pdf,
+            target_value,
+            sample: (),
+        }
+    }
+}
+
+/// Result of [`ReservoirBuilder::stream_detailed`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Acceptance<F = f32> {
+    /// The sample got stored into the reservoir.
+    pub accepted: bool,
+    /// Probability of the sample to be accepted, which is its resampling weight
+    /// relative to the weight sum, including the sample itself.
+    pub probability: F,
+}
+
+/// Result of [`ReservoirBuilder::merge_neighbors`].
+#[derive(Clone, Debug)]
+pub struct NeighborMerge<const N: usize> {
+    /// Index of the neighbor whose sample got selected, if any.
+    pub selected: Option<usize>,
+    canonical_history: u32,
+    histories: [u32; N],
+}
+
+impl<const N: usize> NeighborMerge<N> {
+    /// Compute the history for [`ReservoirBuilder::finish_with_history`],
+    /// only counting the neighbors that could have produced the selected sample.
+    ///
+    /// The `covers_selected` function is called for every neighbor except the selected one,
+    /// and is expected to check if the selected sample is valid in its domain.
+    pub fn unbiased_history(&self, mut covers_selected: impl FnMut(usize) -> bool) -> u32 {
+        let mut history = self.canonical_history;
+        for (index, &neighbor_history) in self.histories.iter().enumerate() {
+            if self.selected == Some(index) || covers_selected(index) {
+                history = history.saturating_add(neighbor_history);
+            }
+        }
+        history
+    }
+
+    /// Finish building the reservoir with the generalized balance heuristic,
+    /// see [`ReservoirBuilder::finish_mis`].
+    ///
+    /// The `target_pdfs` are the target PDFs of the selected sample in the domain
+    /// of every neighbor, zero where it's invalid.
+    pub fn finish_mis<S, F: Scalar>(
+        &self,
+        builder: ReservoirBuilder<S, F>,
+        target_pdfs: [F; N],
+    ) -> Reservoir<S, F> {
+        let canonical_pdf = builder.selected_target_pdf;
+        let selected_pdf = match self.selected {
+            Some(index) => target_pdfs[index],
+            None => canonical_pdf,
+        };
+        let techniques = std::iter::once((self.canonical_history, canonical_pdf))
+            .chain(self.histories.into_iter().zip(target_pdfs));
+        builder.finish_mis(selected_pdf, techniques)
+    }
+}
+
+/// Clamp the contribution weight from above.
+fn clamp_contribution<F: Scalar>(contribution_weight: F, max_contribution: F) -> F {
+    if contribution_weight > max_contribution {
+        max_contribution
+    } else {
+        contribution_weight
+    }
+}
+
+/// Sum of the squared weights, assuming the weight sum is spread evenly over the history.
+fn uniform_square_sum<F: Scalar>(weight_sum: F, history: u32) -> F {
+    if history == 0 {
+        F::ZERO
+    } else {
+        weight_sum * weight_sum / F::from_u32(history)
+    }
+}
+
+/// Scale the history by a decay factor.
+fn decayed_history(history: u32, factor: f32) -> u32 {
+    let scaled = (history as f32 * factor.clamp(0.0, 1.0)).round() as u32;
+    // keep at least one sample, unless all of them are dropped
+    if factor > 0.0 {
+        scaled.max(history.min(1))
+    } else {
+        0
+    }
+}
+
+/// Scale the frames along with the history reduced from `old_history` to `history`.
+fn scaled_frames(frames: u32, old_history: u32, history: u32) -> u32 {
+    if history >= old_history {
+        return frames;
+    }
+    let scaled = (frames as u64 * history as u64 / old_history as u64) as u32;
+    // keep at least one frame, unless all the samples are dropped
+    if history > 0 {
+        scaled.max(frames.min(1))
+    } else {
+        0
+    }
+}
+
+impl<F: Scalar> Reservoir<(), F> {
+    /// Reservoir without any samples, e.g. for initializing static tables.
+    pub const EMPTY: Self = Self::from_parts(0, F::ZERO, F::ZERO, ());
+}
+
+impl<S: Default, F: Scalar> Reservoir<S, F> {
+    /// Construct a reservoir without any samples, e.g. for clearing a grid cell.
+    pub fn empty() -> Self {
+        Self::from_parts(0, F::ZERO, F::ZERO, S::default())
+    }
 }
 
-impl Reservoir {
+impl<S, F: Scalar> Reservoir<S, F> {
     /// Construct a reservoir from a single sample.
-    pub fn from_sample(source_pdf: f32) -> Self {
+    pub fn from_sample(source_pdf: F, sample: S) -> Self {
         Self {
             history: 1,
-            contribution_weight: 1.0 / source_pdf,
+            frames: 1,
+            contribution_weight: F::from_f32(1.0) / source_pdf,
+            target_pdf: F::ZERO,
+            age: 0,
+            sample,
+        }
+    }
+
+    /// Construct a reservoir from its stored parts: the history, the contribution weight,
+    /// the target PDF of the selected sample, and the sample itself,
+    /// e.g. when restoring it from a snapshot.
+    ///
+    /// The history is assumed to come from a single frame,
+    /// and the age of the selected sample starts from zero.
+    pub const fn from_parts(
+        history: u32,
+        contribution_weight: F,
+        target_pdf: F,
+        sample: S,
+    ) -> Self {
+        Self {
+            history,
+            frames: if history == 0 { 0 } else { 1 },
+            contribution_weight,
+            target_pdf,
+            age: 0,
+            sample,
         }
     }
 
+    /// Split the reservoir into the parts accepted by [`Reservoir::from_parts`],
+    /// e.g. for storing it in a GPU buffer.
+    ///
+    /// The frames and the age aren't included, and get lost on the round trip.
+    pub fn into_parts(self) -> (u32, F, F, S) {
+        (
+            self.history,
+            self.contribution_weight,
+            self.target_pdf,
+            self.sample,
+        )
+    }
+
     /// Check if the reservoir has any weight. This is useful in order to
     /// early out from doing expensive computation when reconstructing the
     /// target PDF of a selected sample.
     pub fn has_weight(&self) -> bool {
-        self.contribution_weight != 0.0
+        self.contribution_weight != F::ZERO
     }
 
-    /// Return a copy of the reservoir with clamped history.
-    pub fn with_max_history(&self, max_history: u32) -> Self {
-        Self {
-            history: self.history.min(max_history),
+    /// Check if the reservoir hasn't seen any samples.
+    ///
+    /// Unlike [`Reservoir::has_weight`], this is false for a reservoir
+    /// that has history, but no weight, e.g. after all the samples were invalid.
+    pub fn is_empty(&self) -> bool {
+        self.history == 0
+    }
+
+    /// Check if the contribution weight is above the given threshold.
+    ///
+    /// Unlike [`Reservoir::has_weight`], this also rejects the reservoirs
+    /// with tiny or denormal weights, which contribute nothing in practice.
+    pub fn has_weight_above(&self, epsilon: F) -> bool {
+        self.contribution_weight > epsilon
+    }
+
+    /// Return a copy of the reservoir with the selected sample replaced,
+    /// e.g. by its shift into another domain.
+    pub fn with_sample<T>(&self, sample: T) -> Reservoir<T, F> {
+        Reservoir {
+            history: self.history,
+            frames: self.frames,
             contribution_weight: self.contribution_weight,
+            target_pdf: self.target_pdf,
+            age: self.age,
+            sample,
         }
     }
 
-    /// Convert the reservoir back into a builder state.
-    pub fn to_builder(&self, selected_target_pdf: f32) -> ReservoirBuilder {
+    /// Check if the selected sample is older than the given number of frames,
+    /// so it needs to be re-validated or discarded.
+    pub fn is_expired(&self, max_age: u32) -> bool {
+        self.age > max_age
+    }
+
+    /// Convert the reservoir back into a builder state, moving the sample.
+    fn into_builder(self, selected_target_pdf: F) -> ReservoirBuilder<S, F> {
+        let weight_sum = self.contribution_weight * F::from_u32(self.history) * selected_target_pdf;
         ReservoirBuilder {
             history: self.history,
-            weight_sum: self.contribution_weight * self.history as f32 * selected_target_pdf,
+            weight_sum,
+            selected: self.sample,
             selected_target_pdf,
+            selected_age: self.age,
+            merged_frames: self.frames,
+            has_new_samples: false,
+            precision: Precision::Full,
+            compensation: None,
+            weight_square_sum: uniform_square_sum(weight_sum, self.history),
+            empty_samples: 0,
+            has_selected: weight_sum > F::ZERO,
         }
     }
 
+    /// Return the selected sample.
+    pub fn sample(&self) -> &S {
+        &self.sample
+    }
+
+    /// Return the selected sample for modification.
+    ///
+    /// Any change to its target PDF needs to be accounted for with [`Reservoir::mutate`].
+    pub fn sample_mut(&mut self) -> &mut S {
+        &mut self.sample
+    }
+
     /// Return the contribution weight of the selected sample.
-    pub fn contribution_weight(&self) -> f32 {
+    pub fn contribution_weight(&self) -> F {
         self.contribution_weight
     }
 
-    /// Return the stored history.
+    /// Return the target PDF of the selected sample, as it was when the reservoir
+    /// got finished. Zero if it's unknown, e.g. for [`Reservoir::from_sample`].
+    pub fn target_pdf(&self) -> F {
+        self.target_pdf
+    }
+
+    /// Return the stored history, in samples.
     pub fn history(&self) -> u32 {
         self.history
     }
+
+    /// Return the stored history, in frames.
+    pub fn frames(&self) -> u32 {
+        self.frames
+    }
+
+    /// Return the number of frames since the selected sample was generated.
+    ///
+    /// Unlike the history, this isn't affected by merging with other reservoirs,
+    /// unless their sample gets selected.
+    pub fn age(&self) -> u32 {
+        self.age
+    }
+}
+
+impl<S: Clone, F: Scalar> Reservoir<S, F> {
+    /// Return a copy of the reservoir with clamped history.
+    ///
+    /// The frames are scaled down proportionally.
+    pub fn with_max_history(&self, max_history: u32) -> Self {
+        let history = self.history.min(max_history);
+        Self {
+            history,
+            frames: scaled_frames(self.frames, self.history, history),
+            ..self.clone()
+        }
+    }
+
+    /// Return a copy of the reservoir with the history of a single sample and frame,
+    /// keeping the contribution weight, e.g. to seed a cache where all the inputs
+    /// are treated with equal confidence.
+    ///
+    /// The reservoir alone gives the same estimate, but once merged, it weighs
+    /// as little as one fresh candidate, throwing away the variance reduction
+    /// of its history. This is no more biased than capping the history,
+    /// but the weights are no longer in proportion to the actual sample counts.
+    pub fn canonicalized(&self) -> Self {
+        Self {
+            history: self.history.min(1),
+            frames: self.frames.min(1),
+            ..self.clone()
+        }
+    }
+
+    /// Return a copy of the reservoir with the contribution weight clamped.
+    ///
+    /// This suppresses the fireflies from the unlucky samples with a low source PDF,
+    /// which would otherwise persist through the temporal reuse.
+    /// The estimate loses energy exactly where the clamping happens,
+    /// so it's biased, but only darker, and never brighter.
+    pub fn with_max_contribution(&self, max_contribution: F) -> Self {
+        Self {
+            contribution_weight: clamp_contribution(self.contribution_weight, max_contribution),
+            ..self.clone()
+        }
+    }
+
+    /// Return a copy of the reservoir with the contribution weight multiplied by `factor`,
+    /// keeping the history.
+    ///
+    /// This is useful when the selected sample is shared, e.g. by several shading lobes
+    /// or sub-pixels, each taking its part of the contribution. The result stays unbiased
+    /// as long as the factors don't depend on the selected sample, and the parts of
+    /// a shared sample add up to one. Scaled reservoirs shouldn't be reused
+    /// temporally, since the scaling would compound over the frames.
+    pub fn scaled(&self, factor: F) -> Self {
+        Self {
+            contribution_weight: self.contribution_weight * factor,
+            ..self.clone()
+        }
+    }
+
+    /// Split the reservoir into `count` parts sharing the selected sample,
+    /// e.g. to distribute it across multiple rays or threads.
+    ///
+    /// The history is spread as evenly as possible, and the contribution weight
+    /// proportionally to it, so both add up to the original values,
+    /// and the parts together don't count anything twice.
+    /// The frames are spread evenly over the parts with a non-zero history,
+    /// keeping at least one frame in each of them, so they only add up
+    /// to the original value if there are enough frames to go around.
+    pub fn split(&self, count: u32) -> impl Iterator<Item = Self> + '_ {
+        assert_ne!(count, 0);
+        let spread =
+            |total: u32, parts: u32, index: u32| total / parts + u32::from(index < total % parts);
+        let filled_parts = self.history.min(count);
+        (0..count).map(move |index| {
+            let history = spread(self.history, count, index);
+            let contribution_weight = if self.history == 0 {
+                F::ZERO
+            } else {
+                self.contribution_weight * F::from_u32(history) / F::from_u32(self.history)
+            };
+            let frames = if history == 0 {
+                0
+            } else {
+                spread(self.frames, filled_parts, index).max(self.frames.min(1))
+            };
+            Self {
+                history,
+                frames,
+                contribution_weight,
+                ..self.clone()
+            }
+        })
+    }
+
+    /// Return a copy of the reservoir with both caps of the policy applied.
+    pub fn with_caps(&self, policy: &CapPolicy<F>) -> Self {
+        let history = self.history.min(policy.max_history);
+        Self {
+            history,
+            frames: scaled_frames(self.frames, self.history, history),
+            contribution_weight: clamp_contribution(
+                self.contribution_weight,
+                policy.max_contribution,
+            ),
+            ..self.clone()
+        }
+    }
+
+    /// Return a copy of the reservoir with the history scaled by the given factor in [0, 1],
+    /// see [`ReservoirBuilder::decay_history`].
+    pub fn with_decayed_history(&self, factor: f32) -> Self {
+        let history = decayed_history(self.history, factor);
+        Self {
+            history,
+            frames: scaled_frames(self.frames, self.history, history),
+            ..self.clone()
+        }
+    }
+
+    /// Return a copy of the reservoir with the history scaled by the similarity
+    /// of its domain to the current one, in [0, 1].
+    ///
+    /// This allows a neighbor to contribute partially, instead of being either
+    /// accepted or rejected entirely. The contribution weight is unaffected,
+    /// so the normalization stays correct when merging.
+    pub fn with_similarity(&self, similarity: f32) -> Self {
+        let scale = similarity.clamp(0.0, 1.0);
+        let history = (self.history as f32 * scale).round() as u32;
+        Self {
+            history,
+            frames: scaled_frames(self.frames, self.history, history),
+            ..self.clone()
+        }
+    }
+
+    /// Return a copy of the reservoir with the history clamped to the given number of frames.
+    ///
+    /// The history in samples is scaled down proportionally.
+    pub fn with_max_frames(&self, max_frames: u32) -> Self {
+        if self.frames <= max_frames {
+            return self.clone();
+        }
+        let history = self.history as u64 * max_frames as u64 / self.frames as u64;
+        Self {
+            // keep at least one sample, unless all the frames are dropped
+            history: if max_frames == 0 {
+                0
+            } else {
+                (history as u32).max(1)
+            },
+            frames: max_frames,
+            ..self.clone()
+        }
+    }
+
+    /// Return a copy of the reservoir one frame older.
+    ///
+    /// This is meant to be called once per frame on the reservoirs that are kept around,
+    /// e.g. when storing them in a world-space cache.
+    pub fn aged(&self) -> Self {
+        Self {
+            age: self.age.saturating_add(1),
+            ..self.clone()
+        }
+    }
+
+    /// Return a copy of the reservoir with the selected sample re-validated,
+    /// resetting its age.
+    ///
+    /// The target PDF of the sample is expected to be re-evaluated, and
+    /// the contribution weight adjusted with [`Reservoir::mutate`] if it changed.
+    pub fn revalidated(&self) -> Self {
+        Self {
+            age: 0,
+            ..self.clone()
+        }
+    }
+
+    /// Return a copy of the reservoir with the selected sample replaced
+    /// by its mutation, such as an accepted Metropolis step.
+    ///
+    /// The contribution weight gets rescaled by the ratio of the target PDFs,
+    /// keeping the estimate of the normalization factor intact.
+    pub fn mutate(&self, old_target_pdf: F, new_target_pdf: F) -> Self {
+        Self {
+            contribution_weight: if new_target_pdf > F::ZERO {
+                self.contribution_weight * old_target_pdf / new_target_pdf
+            } else {
+                F::ZERO
+            },
+            target_pdf: new_target_pdf,
+            ..self.clone()
+        }
+    }
+
+    /// Convert the reservoir back into a builder state.
+    pub fn to_builder(&self, selected_target_pdf: F) -> ReservoirBuilder<S, F> {
+        self.clone().into_builder(selected_target_pdf)
+    }
+
+    /// Convert the reservoir back into a builder state, reusing the stored target PDF.
+    ///
+    /// This avoids re-evaluating the target function when the domain hasn't changed,
+    /// e.g. for a static shading point.
+    pub fn to_builder_cached(&self) -> ReservoirBuilder<S, F> {
+        self.to_builder(self.target_pdf)
+    }
 }
 
-impl ReservoirBuilder {
+impl<S: Clone + Default, F: Scalar> Reservoir<S, F> {
+    /// Return a copy of the reservoir, or an empty one if the selected sample is expired.
+    pub fn with_max_age(&self, max_age: u32) -> Self {
+        if self.is_expired(max_age) {
+            Self::default()
+        } else {
+            self.clone()
+        }
+    }
+}
+
+impl<S: Default, F: Scalar> ReservoirBuilder<S, F> {
+    /// Construct a builder without any samples.
+    pub fn empty() -> Self {
+        Self::from_parts(0, F::ZERO, F::ZERO, S::default())
+    }
+}
+
+impl<S, F: Scalar> ReservoirBuilder<S, F> {
+    /// Check if the builder hasn't seen any samples, including the empty ones.
+    pub fn is_empty(&self) -> bool {
+        self.history == 0
+    }
+
+    /// Construct a builder from its raw parts, e.g. when reading it from a GPU buffer.
+    ///
+    /// The history is assumed to come from a single frame,
+    /// and the age of the selected sample starts from zero.
+    pub fn from_parts(history: u32, weight_sum: F, selected_target_pdf: F, selected: S) -> Self {
+        Self {
+            history,
+            weight_sum,
+            selected,
+            selected_target_pdf,
+            selected_age: 0,
+            merged_frames: history.min(1),
+            has_new_samples: false,
+            precision: Precision::Full,
+            compensation: None,
+            weight_square_sum: uniform_square_sum(weight_sum, history),
+            empty_samples: 0,
+            has_selected: weight_sum > F::ZERO,
+        }
+    }
+
+    /// Split the builder into the parts accepted by [`ReservoirBuilder::from_parts`].
+    pub fn into_parts(self) -> (u32, F, F, S) {
+        let weight_sum = self.weight_sum();
+        (
+            self.history,
+            weight_sum,
+            self.selected_target_pdf,
+            self.selected,
+        )
+    }
+
+    /// Return the builder with all the arithmetic rounded to the given precision,
+    /// in order to reproduce the GPU behavior.
+    ///
+    /// This applies to the weights accumulated by this builder,
+    /// including the ones merged from other builders.
+    pub fn with_precision(self, precision: Precision) -> Self {
+        Self { precision, ..self }
+    }
+
+    /// Return the builder with the compensated summation of the weights.
+    ///
+    /// This keeps the weight sum accurate when streaming tens of thousands
+    /// of candidates, without switching to `f64`, at the cost of a few more
+    /// operations per sample.
+    pub fn with_compensated_sum(self) -> Self {
+        Self {
+            compensation: Some(F::ZERO),
+            ..self
+        }
+    }
+
+    /// Round a value to the emulated precision.
+    fn round(&self, value: F) -> F {
+        match self.precision {
+            Precision::Full => value,
+            precision => F::from_f32(precision.round(value.to_f32())),
+        }
+    }
+
+    /// Add a weight to the sum, compensating the rounding error if enabled.
+    fn add_weight(&mut self, weight: F) {
+        let sum = self.round(self.weight_sum + weight);
+        if let Some(compensation) = self.compensation {
+            // Neumaier's variant of the Kahan summation
+            let lost = if self.weight_sum.abs() >= weight.abs() {
+                (self.weight_sum - sum) + weight
+            } else {
+                (weight - sum) + self.weight_sum
+            };
+            self.compensation = Some(self.round(compensation + lost));
+        }
+        self.weight_sum = sum;
+    }
+
+    /// Check the invariants of the builder, if the "strict" feature is enabled.
+    fn check_invariants(&self) {
+        if cfg!(feature = "strict") {
+            let weight_sum = self.weight_sum();
+            assert!(
+                weight_sum.is_finite() && weight_sum >= F::ZERO,
+                "Invalid weight sum {:?}",
+                weight_sum
+            );
+            assert!(
+                self.selected_target_pdf.is_finite() && self.selected_target_pdf >= F::ZERO,
+                "Invalid target PDF {:?}",
+                self.selected_target_pdf
+            );
+            assert!(
+                self.history != 0 || weight_sum == F::ZERO,
+                "Weight sum {:?} without any history",
+                weight_sum
+            );
+        }
+    }
+
     /// Finish building a reservoir.
-    pub fn finish(self) -> Reservoir {
+    pub fn finish(self) -> Reservoir<S, F> {
         let history = self.history;
         self.finish_with_history(history)
     }
 
     /// Finish building a reservoir, using the given history
     /// for weighting (while the stored history is unaffected).
-    pub fn finish_with_history(self, unbiased_history: u32) -> Reservoir {
-        let denom = unbiased_history as f32 * self.selected_target_pdf;
+    pub fn finish_with_history(self, unbiased_history: u32) -> Reservoir<S, F> {
+        self.finish_normalized(F::from_u32(unbiased_history))
+    }
+
+    /// Finish building a reservoir, keeping the diagnostics of the builder,
+    /// see [`FinishOutput`].
+    pub fn finish_detailed(self) -> FinishOutput<S, F> {
+        let history = self.history;
+        self.finish_with_history_detailed(history)
+    }
+
+    /// Finish building a reservoir with an explicit history,
+    /// keeping the diagnostics of the builder, see [`FinishOutput`].
+    pub fn finish_with_history_detailed(self, history: u32) -> FinishOutput<S, F> {
+        self.finish_normalized_detailed(F::from_u32(history))
+    }
+
+    /// Finish building a reservoir with the generalized balance heuristic,
+    /// instead of counting the history of the techniques that could produce the sample.
+    ///
+    /// The `techniques` are pairs of the history and the target PDF of the selected sample
+    /// in the domain of every contributing technique, including this reservoir itself,
+    /// with zero target PDF where the sample is invalid.
+    /// The `selected_pdf` is the target PDF in the domain of the technique that produced it.
+    pub fn finish_mis<I>(self, selected_pdf: F, techniques: I) -> Reservoir<S, F>
+    where
+        I: IntoIterator<Item = (u32, F)>,
+    {
+        let denom = techniques
+            .into_iter()
+            .fold(F::ZERO, |sum, (history, target_pdf)| {
+                sum + F::from_u32(history) * target_pdf
+            });
+        let normalization = if selected_pdf > F::ZERO {
+            denom / selected_pdf
+        } else {
+            F::ZERO
+        };
+        self.finish_normalized(normalization)
+    }
+
+    /// Finish building a reservoir with a custom normalization.
+    ///
+    /// The `contribution_weight` function receives the history, the weight sum,
+    /// and the target PDF of the selected sample, and returns the contribution weight.
+    /// E.g. [`ReservoirBuilder::finish`] divides the weight sum by the product
+    /// of the history and the target PDF.
+    pub fn finish_with(self, contribution_weight: impl FnOnce(u32, F, F) -> F) -> Reservoir<S, F> {
+        self.check_invariants();
+        let contribution_weight = self.round(contribution_weight(
+            self.history,
+            self.weight_sum(),
+            self.selected_target_pdf,
+        ));
+        self.into_reservoir(contribution_weight)
+    }
+
+    fn finish_normalized(self, normalization: F) -> Reservoir<S, F> {
+        self.finish_normalized_detailed(normalization).reservoir
+    }
+
+    fn finish_normalized_detailed(self, normalization: F) -> FinishOutput<S, F> {
+        self.check_invariants();
+        let denominator = self.round(normalization * self.selected_target_pdf);
+        let contribution_weight = if denominator > F::ZERO {
+            self.round(self.weight_sum() / denominator)
+        } else {
+            F::ZERO
+        };
+        let has_selected = self.has_selected;
+        let empty_samples = self.empty_samples;
+        FinishOutput {
+            reservoir: self.into_reservoir(contribution_weight),
+            denominator,
+            has_selected,
+            empty_samples,
+        }
+    }
+
+    fn into_reservoir(self, contribution_weight: F) -> Reservoir<S, F> {
+        if cfg!(feature = "strict") {
+            assert!(
+                contribution_weight.is_finite(),
+                "Invalid contribution weight {:?}",
+                contribution_weight
+            );
+        }
         Reservoir {
             history: self.history,
-            contribution_weight: if denom > 0.0 {
-                self.weight_sum / denom
-            } else {
-                0.0
-            },
+            frames: self.frames(),
+            contribution_weight,
+            target_pdf: self.selected_target_pdf,
+            age: self.selected_age,
+            sample: self.selected,
+        }
+    }
+
+    /// Finish building a reservoir, with the contribution weight clamped,
+    /// see [`Reservoir::with_max_contribution`].
+    pub fn finish_clamped(self, max_contribution: F) -> Reservoir<S, F> {
+        let mut reservoir = self.finish();
+        reservoir.contribution_weight =
+            clamp_contribution(reservoir.contribution_weight, max_contribution);
+        reservoir
+    }
+
+    /// Finish building a reservoir, with both caps of the policy applied.
+    ///
+    /// The history is capped first, keeping the average weight, like [`ReservoirBuilder::clamp_history`].
+    pub fn finish_capped(mut self, policy: &CapPolicy<F>) -> Reservoir<S, F> {
+        if self.history > policy.max_history {
+            self.reweight(policy.max_history);
         }
+        self.finish_clamped(policy.max_contribution)
     }
 
     /// Invalidate the target PDF of the selected sample.
     pub fn invalidate(&mut self) {
-        self.selected_target_pdf = 0.0;
-        self.weight_sum = 0.0;
+        self.selected_target_pdf = F::ZERO;
+        self.weight_sum = F::ZERO;
+        self.compensation = self.compensation.map(|_| F::ZERO);
+        self.weight_square_sum = F::ZERO;
     }
 
     /// Reweight the reservoir as if it had less samples.
     pub fn clamp_history(&mut self, history: u32) {
         assert_ne!(history, 0);
         if self.history > history {
-            let avg = self.weight_sum / self.history as f32;
-            self.history = history;
-            self.weight_sum = avg * history as f32;
+            self.reweight(history);
+        }
+    }
+
+    /// Reweight the reservoir as if it had the history scaled by the given factor in [0, 1].
+    ///
+    /// Unlike [`ReservoirBuilder::clamp_history`], which is a hard cutoff, applying this
+    /// every frame gives a smooth temporal response, with the old samples
+    /// gradually losing their influence.
+    pub fn decay_history(&mut self, factor: f32) {
+        let history = decayed_history(self.history, factor);
+        if self.history > history {
+            self.reweight(history);
         }
     }
 
-    /// Return the stored history.
+    /// Change the history, keeping the average weight.
+    fn reweight(&mut self, history: u32) {
+        let avg = self.average_weight();
+        let old_weight_sum = self.weight_sum();
+        let frames = scaled_frames(self.frames(), self.history, history);
+        self.merged_frames = frames.saturating_sub(self.has_new_samples as u32);
+        self.history = history;
+        self.weight_sum = self.round(avg * F::from_u32(history));
+        self.compensation = self.compensation.map(|_| F::ZERO);
+        self.weight_square_sum = self.scaled_square_sum(self.weight_sum, old_weight_sum);
+        self.check_invariants();
+    }
+
+    /// Return the stored history, in samples.
+    ///
+    /// The history saturates at `u32::MAX` instead of wrapping around,
+    /// when the reservoirs are merged for too long without capping.
     pub fn history(&self) -> u32 {
         self.history
     }
 
+    /// Return the sum of the resampling weights seen so far.
+    ///
+    /// This includes the compensation, if enabled with [`ReservoirBuilder::with_compensated_sum`].
+    pub fn weight_sum(&self) -> F {
+        match self.compensation {
+            Some(compensation) => self.weight_sum + compensation,
+            None => self.weight_sum,
+        }
+    }
+
+    /// Return the effective number of candidates, estimated from the sum of the weights
+    /// and the sum of their squares. It's close to the history when the candidates are
+    /// weighted evenly, and close to 1 when a single one dominates the reservoir.
+    ///
+    /// Merged reservoirs contribute their own estimates, assuming that the finished
+    /// reservoirs had their weight spread evenly over the history.
+    pub fn effective_history(&self) -> F {
+        if self.weight_square_sum > F::ZERO {
+            let weight_sum = self.weight_sum();
+            weight_sum * weight_sum / self.weight_square_sum
+        } else {
+            F::ZERO
+        }
+    }
+
+    /// Sum of the squared weights, with all the weights scaled from `old_sum` to `new_sum`.
+    fn scaled_square_sum(&self, new_sum: F, old_sum: F) -> F {
+        if old_sum > F::ZERO {
+            let scale = new_sum / old_sum;
+            self.weight_square_sum * scale * scale
+        } else {
+            F::ZERO
+        }
+    }
+
+    /// Return the average resampling weight, which is an estimate
+    /// of the normalization factor of the target function.
+    pub fn average_weight(&self) -> F {
+        if self.history == 0 {
+            F::ZERO
+        } else {
+            self.weight_sum() / F::from_u32(self.history)
+        }
+    }
+
+    /// Return the stored history, in frames.
+    ///
+    /// All the samples streamed in count as a single frame.
+    pub fn frames(&self) -> u32 {
+        self.merged_frames
+            .saturating_add(self.has_new_samples as u32)
+    }
+
+    /// Return the selected sample.
+    pub fn selected(&self) -> &S {
+        &self.selected
+    }
+
+    /// Return the selected sample for modification, e.g. to drop its
+    /// contribution after finding it occluded.
+    pub fn selected_mut(&mut self) -> &mut S {
+        &mut self.selected
+    }
+
     /// Stream in a new sample into a reservoir.
     ///
     /// Returns true if the sample got stored into the reservoir.
     ///
     /// The `source_pdf` is a PDF of how the sample was produced.
     /// The `target_value` is how much we consider this sample to be important for the target function.
-    pub fn stream<R: Rng>(&mut self, source_pdf: f32, target_value: f32, random: &mut R) -> bool {
+    /// The `sample` is the payload that gets stored if the sample is selected.
+    ///
+    /// The target value must not be negative, see [`ReservoirBuilder::stream_signed`]
+    /// for the signed integrands. A sample with zero target value is only counted
+    /// in the history, like with [`ReservoirBuilder::add_empty_sample`],
+    /// without drawing a random number.
+    pub fn stream<R: RandomSource>(
+        &mut self,
+        source_pdf: F,
+        target_value: F,
+        sample: S,
+        random: &mut R,
+    ) -> bool {
+        if target_value == F::ZERO {
+            self.add_empty_sample();
+            return false;
+        }
+        self.stream_with_u(source_pdf, target_value, sample, F::gen_unit(random))
+    }
+
+    /// Stream in a new sample into a reservoir, using the given
+    /// uniform random value in [0, 1) for the decision.
+    ///
+    /// This allows the caller to control the random sequence,
+    /// e.g. to use quasi-random numbers or blue noise, to match a GPU
+    /// implementation bit for bit, or to replay the recorded random values.
+    pub fn stream_with_u(&mut self, source_pdf: F, target_value: F, sample: S, u: F) -> bool {
         if true {
             // canonical fast path
-            let weight = target_value / source_pdf;
-            self.history += 1;
-            self.weight_sum += weight;
-            if random.gen::<f32>() * self.weight_sum < weight {
-                self.selected_target_pdf = target_value;
-                true
-            } else {
-                false
+            let weight = self.round(target_value / source_pdf);
+            self.history = self.history.saturating_add(1);
+            if target_value == F::ZERO {
+                self.empty_samples = self.empty_samples.saturating_add(1);
+            }
+            self.has_new_samples = true;
+            self.add_weight(weight);
+            self.weight_square_sum = self.weight_square_sum + weight * weight;
+            let stored = u * self.weight_sum() < weight;
+            if stored {
+                self.selected = sample;
+                self.selected_target_pdf = self.round(target_value);
+                self.selected_age = 0;
+                self.has_selected = true;
             }
+            self.check_invariants();
+            stored
         } else {
             // equivalent semantically, but done via another reservoir
-            let other = Reservoir::from_sample(source_pdf).to_builder(target_value);
-            self.merge(&other, random)
+            let other = Reservoir::from_sample(source_pdf, sample).into_builder(target_value);
+            let stored = self.merge_weights(&other, other.weight_sum(), u);
+            if stored {
+                self.selected = other.selected;
+            }
+            stored
+        }
+    }
+
+    /// Stream in all the candidates of an iterator.
+    ///
+    /// Returns the position of the candidate that ended up selected,
+    /// or `None` if the previously selected sample stayed.
+    pub fn stream_iter<I, R>(&mut self, candidates: I, random: &mut R) -> Option<usize>
+    where
+        I: IntoIterator,
+        I::Item: Into<Candidate<S, F>>,
+        R: RandomSource,
+    {
+        let mut selected = None;
+        for (index, candidate) in candidates.into_iter().enumerate() {
+            if self.stream_candidate(candidate.into(), random) {
+                selected = Some(index);
+            }
+        }
+        selected
+    }
+
+    /// Stream in a new sample given as a candidate, which names
+    /// the source PDF and the target value, so they can't be mixed up.
+    ///
+    /// Returns true if the sample got stored into the reservoir.
+    pub fn stream_candidate<R: RandomSource>(
+        &mut self,
+        candidate: Candidate<S, F>,
+        random: &mut R,
+    ) -> bool {
+        self.stream(
+            candidate.source_pdf,
+            candidate.target_value,
+            candidate.sample,
+            random,
+        )
+    }
+
+    /// Stream in a batch of `N` samples at once, e.g. 8 for the AVX lanes.
+    ///
+    /// The weights are computed and summed up in plain loops over the arrays,
+    /// which the compiler can vectorize. The batch is then reduced like a reservoir
+    /// merged into this one, so it only takes two random values instead of `N`.
+    /// Each sample gets selected with the same probability as when streamed one by one.
+    ///
+    /// Returns the index of the sample in the batch that got selected,
+    /// or `None` if the previously selected sample stayed.
+    pub fn stream_batch<const N: usize, R: RandomSource>(
+        &mut self,
+        source_pdfs: [F; N],
+        target_values: [F; N],
+        samples: [S; N],
+        random: &mut R,
+    ) -> Option<usize> {
+        let mut weights = [F::ZERO; N];
+        for ((weight, &source_pdf), &target_value) in
+            weights.iter_mut().zip(&source_pdfs).zip(&target_values)
+        {
+            *weight = target_value / source_pdf;
+        }
+        if self.precision != Precision::Full {
+            for weight in weights.iter_mut() {
+                *weight = self.round(*weight);
+            }
+        }
+        let (batch_sum, batch_square_sum) = weights
+            .iter()
+            .fold((F::ZERO, F::ZERO), |(sum, square_sum), &weight| {
+                (sum + weight, square_sum + weight * weight)
+            });
+
+        let empty_count = target_values
+            .iter()
+            .filter(|&&target_value| target_value == F::ZERO)
+            .count();
+
+        self.history = self.history.saturating_add(N as u32);
+        self.empty_samples = self.empty_samples.saturating_add(empty_count as u32);
+        self.has_new_samples |= N != 0;
+        self.add_weight(batch_sum);
+        self.weight_square_sum = self.weight_square_sum + batch_square_sum;
+        self.check_invariants();
+        if F::gen_unit(random) * self.weight_sum() >= batch_sum {
+            return None;
+        }
+
+        let mut threshold = F::gen_unit(random) * batch_sum;
+        let index = weights
+            .iter()
+            .position(|&weight| {
+                if threshold < weight {
+                    true
+                } else {
+                    threshold = threshold - weight;
+                    false
+                }
+            })
+            // the rounding errors may push the threshold past the end
+            .or_else(|| weights.iter().rposition(|&weight| weight > F::ZERO))?;
+        self.selected = samples.into_iter().nth(index)?;
+        self.selected_target_pdf = self.round(target_values[index]);
+        self.selected_age = 0;
+        self.has_selected = true;
+        Some(index)
+    }
+
+    /// Stream in a new sample, deferring an expensive validation, e.g. a shadow ray,
+    /// until the sample gets selected.
+    ///
+    /// The visibility is treated as a part of the integrand rather than the target function,
+    /// so the weights are unaffected. An occluded sample stays selected with zero target PDF,
+    /// giving no contribution unless another sample replaces it. This is equivalent to
+    /// validating only the final sample, and stays unbiased, unlike dropping the occluded
+    /// samples from the weight sum.
+    ///
+    /// Returns true if the sample got stored into the reservoir and passed the validation.
+    pub fn stream_visible<R: RandomSource>(
+        &mut self,
+        source_pdf: F,
+        target_value: F,
+        sample: S,
+        is_visible: impl FnOnce(&S) -> bool,
+        random: &mut R,
+    ) -> bool {
+        if !self.stream(source_pdf, target_value, sample, random) {
+            return false;
+        }
+        let visible = is_visible(&self.selected);
+        if !visible {
+            self.selected_target_pdf = F::ZERO;
+        }
+        visible
+    }
+
+    /// Stream in a new sample that survives the Russian roulette with the given probability.
+    ///
+    /// A terminated sample still counts in the history, and a surviving one
+    /// has its resampling weight divided by `survival_prob`, which keeps
+    /// the estimate unbiased.
+    ///
+    /// Returns true if the sample survived and got stored into the reservoir.
+    pub fn stream_rr<R: RandomSource>(
+        &mut self,
+        source_pdf: F,
+        target_value: F,
+        survival_prob: F,
+        sample: S,
+        random: &mut R,
+    ) -> bool {
+        if F::gen_unit(random) < survival_prob {
+            let survival_prob = if survival_prob < F::from_f32(1.0) {
+                survival_prob
+            } else {
+                F::from_f32(1.0)
+            };
+            self.stream(source_pdf * survival_prob, target_value, sample, random)
+        } else {
+            self.add_empty_sample();
+            false
+        }
+    }
+
+    /// Return the probability of a sample with the given resampling weight
+    /// replacing the selected one, without streaming it in.
+    ///
+    /// Useful for deciding if a candidate is worth an expensive validation,
+    /// e.g. with the Russian roulette.
+    pub fn acceptance_probability(&self, weight: F) -> F {
+        let weight = self.round(weight);
+        let weight_sum = self.round(self.weight_sum() + weight);
+        if weight_sum > F::ZERO {
+            weight / weight_sum
+        } else {
+            F::ZERO
         }
     }
 
+    /// Stream in a new sample, also returning the probability of accepting it,
+    /// e.g. for the MIS bookkeeping.
+    pub fn stream_detailed<R: RandomSource>(
+        &mut self,
+        source_pdf: F,
+        target_value: F,
+        sample: S,
+        random: &mut R,
+    ) -> Acceptance<F> {
+        let accepted = self.stream(source_pdf, target_value, sample, random);
+        let weight = self.round(target_value / source_pdf);
+        let weight_sum = self.weight_sum();
+        Acceptance {
+            accepted,
+            probability: if weight_sum > F::ZERO {
+                weight / weight_sum
+            } else {
+                F::ZERO
+            },
+        }
+    }
+
+    /// Stream in a new sample with a signed target value,
+    /// e.g. for the control variates or the difference sampling.
+    ///
+    /// The selection uses the absolute value as the target function, so the
+    /// reservoir stays valid. The sign is not stored: the estimate is the signed
+    /// integrand at the selected sample times the contribution weight, as usual.
+    pub fn stream_signed<R: RandomSource>(
+        &mut self,
+        source_pdf: F,
+        target_value: F,
+        sample: S,
+        random: &mut R,
+    ) -> bool {
+        self.stream(source_pdf, target_value.abs(), sample, random)
+    }
+
+    /// Stream in a new sample, validating the inputs first.
+    ///
+    /// Unlike [`ReservoirBuilder::stream`], a NaN or infinite value is reported
+    /// right away instead of silently poisoning the reservoir.
+    pub fn try_stream<R: RandomSource>(
+        &mut self,
+        source_pdf: F,
+        target_value: F,
+        sample: S,
+        random: &mut R,
+    ) -> Result<bool, InputError<F>> {
+        if !(source_pdf.is_finite() && source_pdf > F::ZERO) {
+            return Err(InputError::SourcePdf(source_pdf));
+        }
+        if !(target_value.is_finite() && target_value >= F::ZERO) {
+            return Err(InputError::TargetValue(target_value));
+        }
+        Ok(self.stream(source_pdf, target_value, sample, random))
+    }
+
     /// Register a sample with zero value.
     pub fn add_empty_sample(&mut self) {
-        self.history += 1;
+        self.history = self.history.saturating_add(1);
+        self.empty_samples = self.empty_samples.saturating_add(1);
+        self.has_new_samples = true;
+    }
+
+    /// Merge the weights of another reservoir, with the given resampling weight,
+    /// returning true if its sample needs to be stored.
+    fn merge_weights(&mut self, other: &Self, other_weight: F, u: F) -> bool {
+        if cfg!(feature = "strict") {
+            other.check_invariants();
+            assert!(
+                self.history.checked_add(other.history).is_some(),
+                "History overflow when merging {} into {}",
+                other.history,
+                self.history
+            );
+        }
+        let other_weight = self.round(other_weight);
+        self.add_weight(other_weight);
+        self.weight_square_sum =
+            self.weight_square_sum + other.scaled_square_sum(other_weight, other.weight_sum());
+        self.history = self.history.saturating_add(other.history);
+        self.merged_frames = self.merged_frames.saturating_add(other.frames());
+        self.empty_samples = self.empty_samples.saturating_add(other.empty_samples);
+        let stored = u * self.weight_sum() < other_weight;
+        if stored {
+            self.selected_target_pdf = self.round(other.selected_target_pdf);
+            self.selected_age = other.selected_age;
+            self.has_selected = true;
+        }
+        self.check_invariants();
+        stored
+    }
+
+    /// Merge a fixed number of neighbor reservoirs into this one,
+    /// without any heap allocations.
+    ///
+    /// Neighbors without weight, or with zero target PDF, only contribute their history.
+    pub fn merge_neighbors<const N: usize, R: RandomSource>(
+        &mut self,
+        neighbors: [NeighborInput<S, F>; N],
+        random: &mut R,
+    ) -> NeighborMerge<N> {
+        let canonical_history = self.history;
+        let histories = neighbors
+            .each_ref()
+            .map(|neighbor| neighbor.reservoir.history);
+        let mut selected = None;
+        for (index, neighbor) in neighbors.into_iter().enumerate() {
+            if neighbor.reservoir.has_weight() && neighbor.target_pdf > F::ZERO {
+                let other = neighbor.reservoir.into_builder(neighbor.target_pdf);
+                if self.merge_weights(&other, other.weight_sum(), F::gen_unit(random)) {
+                    self.selected = other.selected;
+                    selected = Some(index);
+                }
+            } else {
+                self.merge_history(&neighbor.reservoir);
+            }
+        }
+        NeighborMerge {
+            selected,
+            canonical_history,
+            histories,
+        }
     }
 
+    /// Merge history from another reservoir that has no weight.
+    ///
+    /// The sample of the other reservoir is irrelevant, so it can be of any type.
+    pub fn merge_history<T>(&mut self, other: &Reservoir<T, F>) {
+        self.history = self.history.saturating_add(other.history);
+        self.merged_frames = self.merged_frames.saturating_add(other.frames);
+    }
+
+    /// Merge history from another builder, ignoring its weights,
+    /// see [`ReservoirBuilder::merge_history`].
+    pub fn merge_builder_history<T>(&mut self, other: &ReservoirBuilder<T, F>) {
+        self.history = self.history.saturating_add(other.history);
+        self.merged_frames = self.merged_frames.saturating_add(other.frames());
+    }
+
+    /// Merge a plain history count, e.g. read from a GPU buffer,
+    /// see [`ReservoirBuilder::merge_history`].
+    ///
+    /// The history is assumed to come from a single frame.
+    pub fn merge_history_count(&mut self, history: u32) {
+        self.history = self.history.saturating_add(history);
+        self.merged_frames = self.merged_frames.saturating_add(history.min(1));
+    }
+}
+
+impl<S: Clone, F: Scalar> ReservoirBuilder<S, F> {
     /// Merge another reservoir into this one.
     ///
     /// Returns true if the other's sample got stored into the reservoir.
-    pub fn merge<R: Rng>(&mut self, other: &Self, random: &mut R) -> bool {
-        self.weight_sum += other.weight_sum;
-        self.history += other.history;
-        if random.gen::<f32>() * self.weight_sum < other.weight_sum {
-            self.selected_target_pdf = other.selected_target_pdf;
-            true
-        } else {
-            false
+    pub fn merge<R: RandomSource>(&mut self, other: &Self, random: &mut R) -> bool {
+        self.merge_with_u(other, F::gen_unit(random))
+    }
+
+    /// Merge a finished reservoir into this one, e.g. from the previous frame,
+    /// with its history clamped, and the target PDF of its sample evaluated here.
+    ///
+    /// This is a shortcut for `with_max_history`, `to_builder`, and `merge`.
+    /// Returns true if the other's sample got stored into the reservoir.
+    pub fn merge_reservoir<R: RandomSource>(
+        &mut self,
+        other: &Reservoir<S, F>,
+        target_pdf: F,
+        max_history: u32,
+        random: &mut R,
+    ) -> bool {
+        let other = other.with_max_history(max_history).to_builder(target_pdf);
+        self.merge(&other, random)
+    }
+
+    /// Merge another reservoir into this one with an explicit MIS weight,
+    /// as in the generalized RIS.
+    ///
+    /// The resampling weight is `mis_weight * target_pdf * W` of the other reservoir,
+    /// which is its average weight scaled by `mis_weight`. Since the MIS weights
+    /// are already normalized, the result is expected to be finished with
+    /// `finish_with_history(1)`, and every reservoir including the canonical one
+    /// needs to be merged this way.
+    ///
+    /// Returns true if the other's sample got stored into the reservoir.
+    pub fn merge_with_mis<R: RandomSource>(
+        &mut self,
+        other: &Self,
+        mis_weight: F,
+        random: &mut R,
+    ) -> bool {
+        let weight = mis_weight * other.average_weight();
+        let stored = self.merge_weights(other, weight, F::gen_unit(random));
+        if stored {
+            self.selected = other.selected.clone();
         }
+        stored
     }
 
-    /// Merge history from another reservoir that has no weight.
-    pub fn merge_history(&mut self, other: &Reservoir) {
-        self.history += other.history;
+    /// Merge another reservoir with a shift-mapped sample into this one.
+    ///
+    /// The `other` reservoir is expected to hold the sample shifted into this domain,
+    /// with the target PDF evaluated here. Its weight is scaled by the `jacobian`,
+    /// the determinant of the shift from the other domain into this one,
+    /// e.g. the ratio of the cosines and squared distances for a reconnection shift.
+    ///
+    /// Returns true if the other's sample got stored into the reservoir.
+    pub fn merge_with_jacobian<R: RandomSource>(
+        &mut self,
+        other: &Self,
+        jacobian: F,
+        random: &mut R,
+    ) -> bool {
+        let weight = other.weight_sum() * jacobian;
+        let stored = self.merge_weights(other, weight, F::gen_unit(random));
+        if stored {
+            self.selected = other.selected.clone();
+        }
+        stored
+    }
+
+    /// Merge several reservoirs into this one at once.
+    ///
+    /// All the weight sums are accumulated first, followed by a single random draw,
+    /// instead of one per reservoir when merging them one by one.
+    /// Each sample gets selected with the same probability in both cases.
+    ///
+    /// Returns the index of the reservoir whose sample got selected,
+    /// or `None` if the sample of this reservoir stayed.
+    pub fn merge_many<R: RandomSource>(
+        &mut self,
+        others: &[Self],
+        random: &mut R,
+    ) -> Option<usize> {
+        let own_weight = self.weight_sum();
+        for other in others {
+            if cfg!(feature = "strict") {
+                other.check_invariants();
+            }
+            let other_weight = self.round(other.weight_sum());
+            self.add_weight(other_weight);
+            self.weight_square_sum = self.weight_square_sum + other.weight_square_sum;
+            self.history = self.history.saturating_add(other.history);
+            self.merged_frames = self.merged_frames.saturating_add(other.frames());
+            self.empty_samples = self.empty_samples.saturating_add(other.empty_samples);
+        }
+        self.check_invariants();
+
+        let mut threshold = F::gen_unit(random) * self.weight_sum();
+        if threshold < own_weight {
+            return None;
+        }
+        threshold = threshold - own_weight;
+        let index = others
+            .iter()
+            .position(|other| {
+                let other_weight = self.round(other.weight_sum());
+                if threshold < other_weight {
+                    true
+                } else {
+                    threshold = threshold - other_weight;
+                    false
+                }
+            })
+            // the rounding errors may push the threshold past the end
+            .or_else(|| {
+                others
+                    .iter()
+                    .rposition(|other| other.weight_sum() > F::ZERO)
+            })?;
+        let other = &others[index];
+        self.selected = other.selected.clone();
+        self.selected_target_pdf = self.round(other.selected_target_pdf);
+        self.selected_age = other.selected_age;
+        self.has_selected = true;
+        Some(index)
+    }
+
+    /// Merge another reservoir into this one, validating its weights first.
+    ///
+    /// See [`ReservoirBuilder::try_stream`].
+    pub fn try_merge<R: RandomSource>(
+        &mut self,
+        other: &Self,
+        random: &mut R,
+    ) -> Result<bool, InputError<F>> {
+        let weight_sum = other.weight_sum();
+        if !(weight_sum.is_finite() && weight_sum >= F::ZERO) {
+            return Err(InputError::WeightSum(weight_sum));
+        }
+        let target_pdf = other.selected_target_pdf;
+        if !(target_pdf.is_finite() && target_pdf >= F::ZERO) {
+            return Err(InputError::TargetPdf(target_pdf));
+        }
+        Ok(self.merge(other, random))
+    }
+
+    /// Merge another reservoir into this one, using the given
+    /// uniform random value in [0, 1) for the decision,
+    /// see [`ReservoirBuilder::stream_with_u`].
+    ///
+    /// Returns true if the other's sample got stored into the reservoir.
+    pub fn merge_with_u(&mut self, other: &Self, u: F) -> bool {
+        let stored = self.merge_weights(other, other.weight_sum(), u);
+        if stored {
+            self.selected = other.selected.clone();
+        }
+        stored
+    }
+}
+
+impl<F: Scalar> ReservoirBuilder<Option<usize>, F> {
+    /// Stream in a candidate from an indexed pool, such as a light list,
+    /// keeping the index of the selected one.
+    ///
+    /// Returns true if the candidate got stored into the reservoir.
+    pub fn stream_indexed<R: RandomSource>(
+        &mut self,
+        index: usize,
+        source_pdf: F,
+        target_value: F,
+        random: &mut R,
+    ) -> bool {
+        self.stream(source_pdf, target_value, Some(index), random)
+    }
+
+    /// Return the index of the selected candidate, if any.
+    pub fn selected_index(&self) -> Option<usize> {
+        self.selected
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TRIALS: u32 = 100_000;
+    /// Weights of the candidates, with the last one already in the reservoir.
+    const WEIGHTS: [f64; 5] = [1.0, 0.0, 3.0, 6.0, 2.0];
+
+    fn check_frequencies(counts: &[u32; 5]) {
+        let total: f64 = WEIGHTS.iter().sum();
+        for (&count, &weight) in counts.iter().zip(WEIGHTS.iter()) {
+            let frequency = count as f64 / TRIALS as f64;
+            assert!(
+                (frequency - weight / total).abs() < 0.01,
+                "Frequencies {:?} don't match the weights {:?}",
+                counts,
+                WEIGHTS
+            );
+        }
+    }
+
+    fn initial_builder() -> ReservoirBuilder<usize, f64> {
+        ReservoirBuilder::from_parts(1, WEIGHTS[4], WEIGHTS[4], 4)
+    }
+
+    #[test]
+    fn stream_batch_frequencies() {
+        let mut random = RandomStream::new(0, 0);
+        let mut scalar_counts = [0; 5];
+        let mut batch_counts = [0; 5];
+        for _ in 0..TRIALS {
+            let mut builder = initial_builder();
+            for (index, &weight) in WEIGHTS[..4].iter().enumerate() {
+                builder.stream(1.0, weight, index, &mut random);
+            }
+            scalar_counts[*builder.selected()] += 1;
+            let scalar_output = builder.finish_detailed();
+
+            let mut builder = initial_builder();
+            let targets = [WEIGHTS[0], WEIGHTS[1], WEIGHTS[2], WEIGHTS[3]];
+            builder.stream_batch([1.0; 4], targets, [0, 1, 2, 3], &mut random);
+            batch_counts[*builder.selected()] += 1;
+            let batch_output = builder.finish_detailed();
+            assert_eq!(scalar_output.empty_samples, batch_output.empty_samples);
+        }
+        check_frequencies(&scalar_counts);
+        check_frequencies(&batch_counts);
+    }
+
+    #[test]
+    fn split_sums() {
+        for (history, frames, count) in [(10, 4, 3), (10, 1, 3), (2, 2, 3), (7, 7, 1)] {
+            let reservoir = Reservoir {
+                history,
+                frames,
+                contribution_weight: 2.0,
+                target_pdf: 0.5,
+                age: 0,
+                sample: (),
+            };
+            let parts: Vec<_> = reservoir.split(count).collect();
+            assert_eq!(parts.len(), count as usize);
+            for part in parts.iter() {
+                assert_eq!(part.history == 0, part.frames == 0, "{:?}", part);
+            }
+            assert_eq!(parts.iter().map(|part| part.history).sum::<u32>(), history);
+            let weight_sum: f32 = parts.iter().map(|part| part.contribution_weight).sum();
+            assert!((weight_sum - 2.0).abs() < 1e-6);
+            let frame_sum: u32 = parts.iter().map(|part| part.frames).sum();
+            if frames >= history.min(count) {
+                assert_eq!(frame_sum, frames);
+            }
+        }
+    }
+
+    #[test]
+    fn merge_many_frequencies() {
+        let mut random = RandomStream::new(0, 0);
+        let others: Vec<_> = (0..4)
+            .map(|index| ReservoirBuilder::from_parts(1, WEIGHTS[index], 1.0, index))
+            .collect();
+        let mut single_counts = [0; 5];
+        let mut many_counts = [0; 5];
+        for _ in 0..TRIALS {
+            let mut builder = initial_builder();
+            for other in others.iter() {
+                builder.merge(other, &mut random);
+            }
+            single_counts[*builder.selected()] += 1;
+
+            let mut builder = initial_builder();
+            builder.merge_many(&others, &mut random);
+            many_counts[*builder.selected()] += 1;
+        }
+        check_frequencies(&single_counts);
+        check_frequencies(&many_counts);
     }
 }
@@ -0,0 +1,158 @@
+//! Multiple importance sampling for combining the reservoirs.
+
+use crate::{RandomSource, Reservoir, ReservoirBuilder, Scalar};
+
+/// Balance heuristic between two techniques with the given confidences.
+fn balance<F: Scalar>(confidence: F, target_pdf: F, other_confidence: F, other_target_pdf: F) -> F {
+    let own = confidence * target_pdf;
+    let sum = own + other_confidence * other_target_pdf;
+    if sum > F::ZERO {
+        own / sum
+    } else {
+        F::ZERO
+    }
+}
+
+/// Pairwise MIS of the neighbors against a canonical reservoir.
+///
+/// Each neighbor is weighted against the canonical reservoir only,
+/// so the cost is linear in the number of neighbors, while staying unbiased.
+/// The canonical reservoir takes its share in every pair, scaled by the neighbor count.
+/// The histories serve as the confidence weights.
+#[derive(Clone, Debug)]
+pub struct PairwiseMis<S = (), F = f32> {
+    canonical: ReservoirBuilder<S, F>,
+    canonical_mis_weight: F,
+    neighbor_count: u32,
+    defensive: bool,
+    output: ReservoirBuilder<S, F>,
+}
+
+impl<S: Clone + Default, F: Scalar> PairwiseMis<S, F> {
+    /// Start combining the given canonical reservoir with the `neighbor_count` neighbors.
+    pub fn new(canonical: ReservoirBuilder<S, F>, neighbor_count: u32) -> Self {
+        Self {
+            canonical,
+            canonical_mis_weight: F::ZERO,
+            neighbor_count,
+            defensive: false,
+            output: ReservoirBuilder::default(),
+        }
+    }
+
+    /// Use the defensive formulation, which reserves an extra share for the canonical
+    /// reservoir. It stays robust when a neighbor's target function is near-degenerate
+    /// at the canonical sample, at the cost of slightly more noise elsewhere.
+    pub fn with_defensive(mut self) -> Self {
+        self.defensive = true;
+        self
+    }
+
+    /// Number of the techniques sharing the MIS weights.
+    fn technique_count(&self) -> F {
+        let extra = if self.defensive { 1 } else { 0 };
+        F::from_u32(self.neighbor_count + extra)
+    }
+
+    /// Merge a neighbor reservoir.
+    ///
+    /// The `neighbor` is expected to be shifted into the canonical domain, with the target PDF
+    /// evaluated there. The `target_pdf_at_neighbor` is the target PDF of the neighbor's sample
+    /// in its own domain, and `canonical_pdf_at_neighbor` is the target PDF of the canonical
+    /// sample shifted into the neighbor's domain, zero if it's invalid there.
+    ///
+    /// Returns true if the neighbor's sample got stored into the reservoir.
+    pub fn merge<R: RandomSource>(
+        &mut self,
+        neighbor: &ReservoirBuilder<S, F>,
+        target_pdf_at_neighbor: F,
+        canonical_pdf_at_neighbor: F,
+        random: &mut R,
+    ) -> bool {
+        self.canonical_mis_weight = self.canonical_mis_weight
+            + balance(
+                self.canonical_confidence(),
+                self.canonical.selected_target_pdf,
+                F::from_u32(neighbor.history()),
+                canonical_pdf_at_neighbor,
+            );
+        let mis_weight = self.neighbor_mis_weight(neighbor, target_pdf_at_neighbor);
+        self.output.merge_with_mis(neighbor, mis_weight, random)
+    }
+
+    /// Confidence of the canonical reservoir in every pair.
+    fn canonical_confidence(&self) -> F {
+        F::from_u32(self.canonical.history()) / F::from_u32(self.neighbor_count)
+    }
+
+    /// MIS weight of a neighbor's sample, see [`PairwiseMis::merge`].
+    fn neighbor_mis_weight(
+        &self,
+        neighbor: &ReservoirBuilder<S, F>,
+        target_pdf_at_neighbor: F,
+    ) -> F {
+        balance(
+            F::from_u32(neighbor.history()),
+            target_pdf_at_neighbor,
+            self.canonical_confidence(),
+            neighbor.selected_target_pdf,
+        ) / self.technique_count()
+    }
+
+    /// MIS weight of the canonical sample, after all the neighbors are merged.
+    fn canonical_mis_weight(&self) -> F {
+        if self.defensive {
+            (F::from_f32(1.0) + self.canonical_mis_weight) / self.technique_count()
+        } else if self.neighbor_count == 0 {
+            F::from_f32(1.0)
+        } else {
+            self.canonical_mis_weight / F::from_u32(self.neighbor_count)
+        }
+    }
+
+    /// Merge the canonical reservoir and finish building the result.
+    pub fn finish<R: RandomSource>(mut self, random: &mut R) -> Reservoir<S, F> {
+        let mis_weight = self.canonical_mis_weight();
+        self.output
+            .merge_with_mis(&self.canonical, mis_weight, random);
+        self.output.finish_with_history(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RandomStream;
+
+    /// Check that the MIS weights of a sample in all the techniques sum up to one.
+    fn check_weight_sum(defensive: bool) {
+        // target PDFs of the same sample in the domains of the neighbors, and their histories
+        let neighbors = [(0.5, 20), (2.0, 5), (0.0, 10), (1.0, 1)];
+        let canonical_pdf = 1.5;
+        let canonical = ReservoirBuilder::<(), f64>::from_parts(8, 1.0, canonical_pdf, ());
+        let mut mis = PairwiseMis::new(canonical, neighbors.len() as u32);
+        if defensive {
+            mis = mis.with_defensive();
+        }
+        let mut random = RandomStream::new(0, 0);
+        let mut sum = 0.0;
+        for &(target_pdf, history) in neighbors.iter() {
+            // the sample is shifted into the canonical domain, so it has the canonical PDF there
+            let neighbor = ReservoirBuilder::from_parts(history, 1.0, canonical_pdf, ());
+            sum += mis.neighbor_mis_weight(&neighbor, target_pdf);
+            mis.merge(&neighbor, target_pdf, target_pdf, &mut random);
+        }
+        sum += mis.canonical_mis_weight();
+        assert!((sum - 1.0).abs() < 1e-12, "MIS weights sum up to {}", sum);
+    }
+
+    #[test]
+    fn pairwise_weight_sum() {
+        check_weight_sum(false);
+    }
+
+    #[test]
+    fn defensive_pairwise_weight_sum() {
+        check_weight_sum(true);
+    }
+}
@@ -0,0 +1,399 @@
+//! Reservoirs holding multiple samples.
+
+use crate::{RandomSource, Reservoir, ReservoirBuilder, Scalar};
+use std::cmp::Ordering;
+
+/// Builder for `K` independent reservoirs, fed by the same stream of samples.
+///
+/// This selects `K` samples with replacement. The storage is a plain array
+/// with `K` known at compile time, so there are no heap allocations,
+/// and it maps directly to fixed-size arrays in shading languages.
+#[derive(Clone, Debug)]
+pub struct MultiReservoirBuilder<const K: usize, S = (), F = f32> {
+    slots: [ReservoirBuilder<S, F>; K],
+}
+
+impl<const K: usize, S: Default, F: Scalar> Default for MultiReservoirBuilder<K, S, F> {
+    fn default() -> Self {
+        Self {
+            slots: core::array::from_fn(|_| ReservoirBuilder::default()),
+        }
+    }
+}
+
+impl<const K: usize, S: Clone, F: Scalar> MultiReservoirBuilder<K, S, F> {
+    /// Stream in a new sample into every slot.
+    ///
+    /// Returns a mask of the slots that stored the sample.
+    pub fn stream<R: RandomSource>(
+        &mut self,
+        source_pdf: F,
+        target_value: F,
+        sample: S,
+        random: &mut R,
+    ) -> [bool; K] {
+        let mut stored = [false; K];
+        for (slot, stored) in self.slots.iter_mut().zip(stored.iter_mut()) {
+            *stored = slot.stream(source_pdf, target_value, sample.clone(), random);
+        }
+        stored
+    }
+
+    /// Register a sample with zero value in every slot.
+    pub fn add_empty_sample(&mut self) {
+        for slot in self.slots.iter_mut() {
+            slot.add_empty_sample();
+        }
+    }
+
+    /// Return the builder of a single slot.
+    pub fn slot(&self, index: usize) -> &ReservoirBuilder<S, F> {
+        &self.slots[index]
+    }
+
+    /// Return the mutable builder of a single slot, e.g. for merging.
+    pub fn slot_mut(&mut self, index: usize) -> &mut ReservoirBuilder<S, F> {
+        &mut self.slots[index]
+    }
+
+    /// Finish building all the reservoirs.
+    pub fn finish(self) -> [Reservoir<S, F>; K] {
+        self.slots.map(ReservoirBuilder::finish)
+    }
+}
+
+/// Sample selected by [`PriorityReservoirBuilder`].
+#[derive(Clone, Debug)]
+struct PriorityEntry<S, F> {
+    priority: F,
+    weight: F,
+    target_pdf: F,
+    sample: S,
+}
+
+/// Builder for a reservoir of `K` distinct samples, selected without replacement.
+///
+/// Uses priority sampling: every candidate gets a random priority
+/// of its resampling weight divided by a uniform random value, and the `K` highest
+/// priorities are kept. The highest of the dropped priorities serves as a threshold
+/// for the contribution weights, making the estimate unbiased.
+///
+/// Unlike [`MultiReservoirBuilder`], the contributions of the finished reservoirs
+/// are summed up rather than averaged, since each covers its own part of the candidates.
+#[derive(Clone, Debug)]
+pub struct PriorityReservoirBuilder<const K: usize, S = (), F = f32> {
+    entries: [Option<PriorityEntry<S, F>>; K],
+    threshold: F,
+    history: u32,
+}
+
+impl<const K: usize, S, F: Scalar> Default for PriorityReservoirBuilder<K, S, F> {
+    fn default() -> Self {
+        let () = Self::NON_EMPTY;
+        Self {
+            entries: core::array::from_fn(|_| None),
+            threshold: F::ZERO,
+            history: 0,
+        }
+    }
+}
+
+impl<const K: usize, S, F: Scalar> PriorityReservoirBuilder<K, S, F> {
+    /// Rejects the builders without any slots at compile time.
+    const NON_EMPTY: () = assert!(K > 0, "The reservoir needs at least one slot");
+
+    fn raise_threshold(&mut self, priority: F) {
+        if priority > self.threshold {
+            self.threshold = priority;
+        }
+    }
+
+    /// Insert an entry if its priority is high enough,
+    /// raising the threshold by whatever gets dropped.
+    fn insert(&mut self, entry: PriorityEntry<S, F>) -> bool {
+        let slot = self
+            .entries
+            .iter_mut()
+            .min_by(|a, b| match (a, b) {
+                (None, _) => Ordering::Less,
+                (Some(_), None) => Ordering::Greater,
+                (Some(a), Some(b)) => a
+                    .priority
+                    .partial_cmp(&b.priority)
+                    .unwrap_or(Ordering::Equal),
+            })
+            .unwrap();
+        match *slot {
+            Some(ref lowest) if lowest.priority >= entry.priority => {
+                self.raise_threshold(entry.priority);
+                false
+            }
+            _ => {
+                if let Some(lowest) = slot.replace(entry) {
+                    self.raise_threshold(lowest.priority);
+                }
+                true
+            }
+        }
+    }
+
+    /// Stream in a new sample.
+    ///
+    /// Returns true if the sample got stored into the reservoir.
+    pub fn stream<R: RandomSource>(
+        &mut self,
+        source_pdf: F,
+        target_value: F,
+        sample: S,
+        random: &mut R,
+    ) -> bool {
+        self.history = self.history.saturating_add(1);
+        let weight = target_value / source_pdf;
+        if weight <= F::ZERO {
+            return false;
+        }
+        // the uniform value is in (0, 1], so that the priority stays finite
+        let u = F::from_f32(1.0) - F::gen_unit(random);
+        self.insert(PriorityEntry {
+            priority: weight / u,
+            weight,
+            target_pdf: target_value,
+            sample,
+        })
+    }
+
+    /// Register a sample with zero value.
+    pub fn add_empty_sample(&mut self) {
+        self.history = self.history.saturating_add(1);
+    }
+
+    /// Return the stored history, in samples.
+    pub fn history(&self) -> u32 {
+        self.history
+    }
+
+    /// Return the selected samples, in no particular order.
+    pub fn selected(&self) -> impl Iterator<Item = &S> {
+        self.entries.iter().flatten().map(|entry| &entry.sample)
+    }
+
+    /// Finish building the reservoirs, one per selected sample.
+    ///
+    /// The slots that didn't get a sample have no weight.
+    pub fn finish(self) -> [Reservoir<S, F>; K]
+    where
+        S: Default,
+    {
+        let history = self.history;
+        let threshold = self.threshold;
+        self.entries.map(|entry| match entry {
+            Some(entry) => {
+                let weight = if entry.weight > threshold {
+                    entry.weight
+                } else {
+                    threshold
+                };
+                Reservoir {
+                    history,
+                    frames: history.min(1),
+                    contribution_weight: weight / (F::from_u32(history) * entry.target_pdf),
+                    target_pdf: entry.target_pdf,
+                    age: 0,
+                    sample: entry.sample,
+                }
+            }
+            None => Reservoir {
+                history,
+                frames: history.min(1),
+                ..Reservoir::default()
+            },
+        })
+    }
+}
+
+impl<const K: usize, S: Clone, F: Scalar> PriorityReservoirBuilder<K, S, F> {
+    /// Merge another builder into this one, keeping the highest priorities of both.
+    pub fn merge(&mut self, other: &Self) {
+        self.history = self.history.saturating_add(other.history);
+        self.raise_threshold(other.threshold);
+        for entry in other.entries.iter().flatten() {
+            self.insert(entry.clone());
+        }
+    }
+}
+
+/// `N` independent reservoirs stored contiguously, operated lanewise,
+/// e.g. for a scanline or a group of SIMD lanes.
+///
+/// Every operation takes the per-lane inputs as arrays, including
+/// the uniform random values, so the lanes don't share a random sequence.
+#[derive(Clone, Debug)]
+pub struct ReservoirArray<const N: usize, S = (), F = f32> {
+    lanes: [ReservoirBuilder<S, F>; N],
+}
+
+impl<const N: usize, S: Default, F: Scalar> Default for ReservoirArray<N, S, F> {
+    fn default() -> Self {
+        Self {
+            lanes: core::array::from_fn(|_| ReservoirBuilder::default()),
+        }
+    }
+}
+
+impl<const N: usize, S, F: Scalar> ReservoirArray<N, S, F> {
+    /// Stream in a new sample into every lane, see [`ReservoirBuilder::stream_with_u`].
+    ///
+    /// Returns a mask of the lanes that stored their sample.
+    pub fn stream(
+        &mut self,
+        source_pdfs: [F; N],
+        target_values: [F; N],
+        samples: [S; N],
+        u: [F; N],
+    ) -> [bool; N] {
+        let mut stored = [false; N];
+        for (index, sample) in samples.into_iter().enumerate() {
+            stored[index] = self.lanes[index].stream_with_u(
+                source_pdfs[index],
+                target_values[index],
+                sample,
+                u[index],
+            );
+        }
+        stored
+    }
+
+    /// Return the builder of a single lane.
+    pub fn lane(&self, index: usize) -> &ReservoirBuilder<S, F> {
+        &self.lanes[index]
+    }
+
+    /// Return the mutable builder of a single lane.
+    pub fn lane_mut(&mut self, index: usize) -> &mut ReservoirBuilder<S, F> {
+        &mut self.lanes[index]
+    }
+
+    /// Finish building all the reservoirs.
+    pub fn finish(self) -> [Reservoir<S, F>; N] {
+        self.lanes.map(ReservoirBuilder::finish)
+    }
+}
+
+impl<const N: usize, S: Clone, F: Scalar> ReservoirArray<N, S, F> {
+    /// Merge the lanes of another array into the matching lanes of this one,
+    /// see [`ReservoirBuilder::merge_with_u`].
+    ///
+    /// Returns a mask of the lanes that stored the other's sample.
+    pub fn merge(&mut self, other: &Self, u: [F; N]) -> [bool; N] {
+        let mut stored = [false; N];
+        for (index, lane) in self.lanes.iter_mut().enumerate() {
+            stored[index] = lane.merge_with_u(&other.lanes[index], u[index]);
+        }
+        stored
+    }
+}
+
+/// Builder for a reservoir over `K` disjoint strata of the domain,
+/// e.g. hemisphere sectors or light clusters.
+///
+/// Every stratum has its own reservoir, which guarantees that each of them is covered,
+/// instead of a single global winner. Each stratum is expected to be sampled on its own,
+/// with the source PDF restricted to it.
+#[derive(Clone, Debug)]
+pub struct StratifiedReservoirBuilder<const K: usize, S = (), F = f32> {
+    strata: [ReservoirBuilder<S, F>; K],
+}
+
+impl<const K: usize, S: Default, F: Scalar> Default for StratifiedReservoirBuilder<K, S, F> {
+    fn default() -> Self {
+        Self {
+            strata: core::array::from_fn(|_| ReservoirBuilder::default()),
+        }
+    }
+}
+
+impl<const K: usize, S, F: Scalar> StratifiedReservoirBuilder<K, S, F> {
+    /// Stream in a new sample into the given stratum, see [`ReservoirBuilder::stream`].
+    pub fn stream<R: RandomSource>(
+        &mut self,
+        stratum: usize,
+        source_pdf: F,
+        target_value: F,
+        sample: S,
+        random: &mut R,
+    ) -> bool {
+        self.strata[stratum].stream(source_pdf, target_value, sample, random)
+    }
+
+    /// Register a sample with zero value in the given stratum.
+    pub fn add_empty_sample(&mut self, stratum: usize) {
+        self.strata[stratum].add_empty_sample();
+    }
+
+    /// Return the builder of a single stratum.
+    pub fn stratum(&self, index: usize) -> &ReservoirBuilder<S, F> {
+        &self.strata[index]
+    }
+
+    /// Return the mutable builder of a single stratum, e.g. for merging.
+    pub fn stratum_mut(&mut self, index: usize) -> &mut ReservoirBuilder<S, F> {
+        &mut self.strata[index]
+    }
+
+    /// Finish building the reservoirs of all the strata separately.
+    pub fn finish_strata(self) -> [Reservoir<S, F>; K] {
+        self.strata.map(ReservoirBuilder::finish)
+    }
+}
+
+impl<const K: usize, S: Clone + Default, F: Scalar> StratifiedReservoirBuilder<K, S, F> {
+    /// Finish building a single reservoir, resampling one of the strata.
+    ///
+    /// The strata don't overlap, so each of them takes its estimate of the normalization
+    /// as the resampling weight, and the result sums them up instead of averaging.
+    pub fn finish<R: RandomSource>(self, random: &mut R) -> Reservoir<S, F> {
+        let mut builder = ReservoirBuilder::default();
+        for stratum in self.strata.iter() {
+            builder.merge_with_mis(stratum, F::from_f32(1.0), random);
+        }
+        builder.finish_with_history(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::RandomStream;
+
+    #[test]
+    fn priority_frequencies() {
+        const TRIALS: u32 = 100_000;
+        const WEIGHTS: [f64; 5] = [1.0, 0.0, 3.0, 6.0, 2.0];
+        let mut random = RandomStream::new(0, 0);
+        let mut counts = [0; 5];
+        let mut estimates = [0.0; 5];
+        for _ in 0..TRIALS {
+            let mut builder = PriorityReservoirBuilder::<2, usize, f64>::default();
+            for (index, &weight) in WEIGHTS.iter().enumerate() {
+                builder.stream(1.0, weight, index, &mut random);
+            }
+            for reservoir in builder.finish() {
+                let (history, contribution_weight, target_pdf, index) = reservoir.into_parts();
+                counts[index] += 1;
+                estimates[index] += contribution_weight * target_pdf * history as f64;
+            }
+        }
+        assert_eq!(counts[1], 0);
+        // every candidate is expected to contribute its own weight
+        for (&estimate, &weight) in estimates.iter().zip(WEIGHTS.iter()) {
+            assert!(
+                (estimate / TRIALS as f64 - weight).abs() < 0.05,
+                "Estimates {:?} don't match the weights {:?}",
+                estimates.map(|estimate| estimate / TRIALS as f64),
+                WEIGHTS
+            );
+        }
+        // the selection is more likely for the heavier candidates
+        assert!(counts[0] < counts[4] && counts[4] < counts[2] && counts[2] < counts[3]);
+    }
+}
@@ -0,0 +1,93 @@
+//! Hooks for observing the changes of the selected sample.
+
+use crate::{RandomSource, ReservoirBuilder, Scalar};
+
+/// Change of the selected sample of a reservoir.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct SelectionChange<F = f32> {
+    /// Target PDF of the previously selected sample, zero if there was none.
+    pub old_target_pdf: F,
+    /// Target PDF of the newly selected sample.
+    pub new_target_pdf: F,
+    /// Resampling weight of the new sample, which won the selection.
+    pub weight: F,
+    /// Sum of all the resampling weights, including the new one.
+    pub weight_sum: F,
+}
+
+/// Builder of a reservoir, calling a hook every time the selected sample changes.
+///
+/// The hook receives the change, followed by the old and the new selected samples.
+/// The old sample is cloned before every decision, so observing is meant
+/// for debugging and visualization rather than the hot path.
+///
+/// Created by [`ReservoirBuilder::observe`].
+pub struct Observed<'a, S, F, H> {
+    builder: &'a mut ReservoirBuilder<S, F>,
+    hook: H,
+}
+
+impl<S, F: Scalar> ReservoirBuilder<S, F> {
+    /// Observe the changes of the selected sample with the given hook,
+    /// e.g. for visualization or logging.
+    pub fn observe<H>(&mut self, hook: H) -> Observed<'_, S, F, H>
+    where
+        H: FnMut(&SelectionChange<F>, &S, &S),
+    {
+        Observed {
+            builder: self,
+            hook,
+        }
+    }
+}
+
+impl<S: Clone, F: Scalar, H: FnMut(&SelectionChange<F>, &S, &S)> Observed<'_, S, F, H> {
+    fn notify(&mut self, old_target_pdf: F, old_sample: &S, weight: F) {
+        let change = SelectionChange {
+            old_target_pdf,
+            new_target_pdf: self.builder.selected_target_pdf,
+            weight,
+            weight_sum: self.builder.weight_sum(),
+        };
+        (self.hook)(&change, old_sample, self.builder.selected());
+    }
+
+    /// Stream in a new sample, see [`ReservoirBuilder::stream`].
+    pub fn stream<R: RandomSource>(
+        &mut self,
+        source_pdf: F,
+        target_value: F,
+        sample: S,
+        random: &mut R,
+    ) -> bool {
+        let old_target_pdf = self.builder.selected_target_pdf;
+        let old_sample = self.builder.selected().clone();
+        let stored = self
+            .builder
+            .stream(source_pdf, target_value, sample, random);
+        if stored {
+            self.notify(old_target_pdf, &old_sample, target_value / source_pdf);
+        }
+        stored
+    }
+
+    /// Merge another reservoir, see [`ReservoirBuilder::merge`].
+    pub fn merge<R: RandomSource>(
+        &mut self,
+        other: &ReservoirBuilder<S, F>,
+        random: &mut R,
+    ) -> bool {
+        let old_target_pdf = self.builder.selected_target_pdf;
+        let old_sample = self.builder.selected().clone();
+        let stored = self.builder.merge(other, random);
+        if stored {
+            self.notify(old_target_pdf, &old_sample, other.weight_sum());
+        }
+        stored
+    }
+
+    /// Register a sample with zero value, see [`ReservoirBuilder::add_empty_sample`].
+    pub fn add_empty_sample(&mut self) {
+        self.builder.add_empty_sample();
+    }
+}
@@ -0,0 +1,54 @@
+//! Emulation of the GPU floating point precision.
+
+/// Precision of the reservoir arithmetic.
+///
+/// GPUs often flush the denormals to zero, and the reservoirs may be stored
+/// in half precision, which can zero out small weights or quantize the PDFs.
+/// Rounding every intermediate result to the chosen precision reproduces
+/// these artifacts on the CPU.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash)]
+pub enum Precision {
+    /// Regular f32 arithmetic.
+    #[default]
+    Full,
+    /// f32 with the denormals flushed to zero.
+    FlushToZero,
+    /// f16, with the round-to-nearest-even conversion.
+    Half,
+}
+
+impl Precision {
+    /// Round a value to this precision.
+    pub fn round(self, value: f32) -> f32 {
+        match self {
+            Self::Full => value,
+            Self::FlushToZero if value.is_subnormal() => 0.0f32.copysign(value),
+            Self::FlushToZero => value,
+            Self::Half => to_half(value),
+        }
+    }
+}
+
+/// Round a value to the nearest f16, returning it as f32.
+fn to_half(value: f32) -> f32 {
+    const MAX: f32 = 65504.0;
+    const MIN_NORMAL: f32 = 6.1035156e-5; // 2^-14
+    const DENORMAL_STEP: f32 = 5.9604645e-8; // 2^-24
+    let magnitude = value.abs();
+    if !value.is_finite() {
+        value
+    } else if magnitude < MIN_NORMAL {
+        // denormals have a fixed step
+        (value / DENORMAL_STEP).round_ties_even() * DENORMAL_STEP
+    } else {
+        // drop 13 bits of the mantissa, rounding to the nearest even
+        let bits = value.to_bits();
+        let rounded = bits + 0x0FFF + ((bits >> 13) & 1);
+        let result = f32::from_bits(rounded & !0x1FFF);
+        if result.abs() > MAX {
+            f32::INFINITY.copysign(value)
+        } else {
+            result
+        }
+    }
+}
@@ -0,0 +1,112 @@
+//! Quasi-random streams of low-discrepancy sequences.
+
+use crate::{random::pcg_hash, RandomSource};
+
+/// Permutation of the bits, where every bit only depends on the lower ones,
+/// from "Practical Hash-based Owen Scrambling" by Burley.
+fn laine_karras_permutation(mut x: u32, seed: u32) -> u32 {
+    x = x.wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x
+}
+
+/// Owen scrambling of a fixed-point value in [0, 1).
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    laine_karras_permutation(x.reverse_bits(), seed).reverse_bits()
+}
+
+/// First two dimensions of the Sobol sequence, as fixed-point values.
+fn sobol(index: u32) -> [u32; 2] {
+    let mut y = 0;
+    let mut direction = 1 << 31;
+    let mut bits = index;
+    while bits != 0 {
+        if bits & 1 != 0 {
+            y ^= direction;
+        }
+        bits >>= 1;
+        direction ^= direction >> 1;
+    }
+    [index.reverse_bits(), y]
+}
+
+/// R2 sequence by Roberts, as fixed-point values.
+fn r2(index: u32) -> [u32; 2] {
+    // inverse powers of the plastic number
+    const ALPHA: [f64; 2] = [0.7548776662466927, 0.5698402909980532];
+    ALPHA.map(|alpha| ((0.5 + alpha * index as f64).fract() * 4294967296.0) as u32)
+}
+
+/// Low-discrepancy sequence of a [`QuasiRandomStream`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QuasiSequence {
+    /// Sobol sequence with Owen scrambling.
+    Sobol,
+    /// R2 sequence with a random rotation.
+    R2,
+}
+
+/// Stream of quasi-random numbers, to drive the reservoir decisions
+/// with less variance than the pseudo-random ones, especially for small candidate counts.
+///
+/// The index is the number of the sample within the pixel, e.g. the frame,
+/// and the seed decorrelates the pixels. Like with [`RandomStream`], every random decision
+/// consumes the next dimension, and passes can start at fixed dimensions.
+/// The dimensions are padded: every pair of them is a separate 2D sequence,
+/// with the index shuffled within the power-of-two blocks, which keeps the points
+/// well distributed at the power-of-two sample counts.
+///
+/// [`RandomStream`]: crate::RandomStream
+#[derive(Clone, Debug)]
+pub struct QuasiRandomStream {
+    sequence: QuasiSequence,
+    index: u32,
+    seed: u32,
+    dimension: u32,
+}
+
+impl QuasiRandomStream {
+    /// Create a new stream for the given sample index and seed, starting at dimension 0.
+    pub fn new(sequence: QuasiSequence, index: u32, seed: u32) -> Self {
+        Self {
+            sequence,
+            index,
+            seed,
+            dimension: 0,
+        }
+    }
+
+    /// Return the dimension of the next random value.
+    pub fn dimension(&self) -> u32 {
+        self.dimension
+    }
+
+    /// Jump to the given dimension.
+    pub fn set_dimension(&mut self, dimension: u32) {
+        self.dimension = dimension;
+    }
+
+    /// Return the value of the given dimension, in [0, 1).
+    pub fn sample(&self, dimension: u32) -> f32 {
+        let pair_seed = pcg_hash(self.seed ^ pcg_hash(dimension / 2));
+        let index = nested_uniform_scramble(self.index, pair_seed);
+        let axis = (dimension % 2) as usize;
+        let axis_seed = pcg_hash(pair_seed ^ axis as u32);
+        let value = match self.sequence {
+            QuasiSequence::Sobol => nested_uniform_scramble(sobol(index)[axis], axis_seed),
+            QuasiSequence::R2 => r2(index)[axis].wrapping_add(axis_seed),
+        };
+        (value >> 8) as f32 / 16777216.0
+    }
+}
+
+impl RandomSource for QuasiRandomStream {
+    fn uniform(&mut self) -> f32 {
+        let value = self.sample(self.dimension);
+        self.dimension += 1;
+        value
+    }
+}
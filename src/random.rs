@@ -0,0 +1,128 @@
+//! Sources of the random values, and the streams indexed by the pixel, frame, and dimension.
+
+use rand_core::RngCore;
+
+/// Source of the uniform random values driving the reservoir decisions.
+///
+/// Implemented for every `rand_core` generator, and can be implemented directly
+/// for GPU-style hash generators or the random streams provided by an engine.
+pub trait RandomSource {
+    /// Return a uniform random value in [0, 1).
+    fn uniform(&mut self) -> f32;
+
+    /// Return a uniform random value in [0, 1), for the `f64` weights.
+    ///
+    /// Widens [`RandomSource::uniform`] by default.
+    fn uniform_f64(&mut self) -> f64 {
+        self.uniform() as f64
+    }
+}
+
+/// Same conversion as the `Standard` distribution of `rand`,
+/// keeping as many upper bits as fit into the mantissa.
+impl<R: RngCore + ?Sized> RandomSource for R {
+    fn uniform(&mut self) -> f32 {
+        (self.next_u32() >> 8) as f32 / (1u32 << 24) as f32
+    }
+
+    fn uniform_f64(&mut self) -> f64 {
+        (self.next_u64() >> 11) as f64 / (1u64 << 53) as f64
+    }
+}
+
+/// Hash function from "Hash Functions for GPU Rendering" by Jarzynski and Olano.
+pub(crate) fn pcg_hash(input: u32) -> u32 {
+    let state = input.wrapping_mul(747796405).wrapping_add(2891336453);
+    let word = ((state >> ((state >> 28) + 4)) ^ state).wrapping_mul(277803737);
+    (word >> 22) ^ word
+}
+
+/// Stream of random numbers, where every value is a hash
+/// of the (pixel, frame, dimension) tuple.
+///
+/// Every random decision consumes the next dimension, so the dimensions used
+/// within a pass depend on its candidates: e.g. streaming a candidate with zero
+/// target value doesn't draw anything, see [`ReservoirBuilder::stream`].
+/// Passes can start at fixed dimensions with [`RandomStream::set_dimension`],
+/// so that adding, removing, or skipping a candidate doesn't shift
+/// the random values of the later passes.
+///
+/// The same values can be reproduced on the GPU with the same hash,
+/// see [`RandomStream::hash`]. A uniform `f32` decision, as used with the `f32` weights,
+/// keeps the upper 24 bits of the hash. In WGSL, this matches:
+/// ```text
+/// fn pcg_hash(input: u32) -> u32 {
+///     let state = input * 747796405u + 2891336453u;
+///     let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+///     return (word >> 22u) ^ word;
+/// }
+/// fn random_uniform(pixel: u32, frame: u32, dimension: u32) -> f32 {
+///     let hash = pcg_hash(pcg_hash(pcg_hash(pixel) ^ frame) ^ dimension);
+///     return f32(hash >> 8u) / 16777216.0;
+/// }
+/// ```
+/// The `f64` decisions consume two dimensions per value, and have no such counterpart.
+/// To stay in sync, the GPU side needs to skip the draws for the zero target values too.
+///
+/// [`ReservoirBuilder::stream`]: crate::ReservoirBuilder::stream
+#[derive(Clone, Debug)]
+pub struct RandomStream {
+    pixel: u32,
+    frame: u32,
+    dimension: u32,
+}
+
+impl RandomStream {
+    /// Create a new stream for a pixel in a frame, starting at dimension 0.
+    ///
+    /// The frame can also be any per-frame seed.
+    pub fn new(pixel: u32, frame: u32) -> Self {
+        Self {
+            pixel,
+            frame,
+            dimension: 0,
+        }
+    }
+
+    /// Return the dimension of the next random value.
+    pub fn dimension(&self) -> u32 {
+        self.dimension
+    }
+
+    /// Jump to the given dimension.
+    pub fn set_dimension(&mut self, dimension: u32) {
+        self.dimension = dimension;
+    }
+
+    /// Return the hash of a (pixel, frame, dimension) tuple, which is the raw value
+    /// produced by the stream at that dimension, e.g. to compare against a GPU buffer.
+    pub fn hash(pixel: u32, frame: u32, dimension: u32) -> u32 {
+        pcg_hash(pcg_hash(pcg_hash(pixel) ^ frame) ^ dimension)
+    }
+}
+
+impl RngCore for RandomStream {
+    fn next_u32(&mut self) -> u32 {
+        let hash = Self::hash(self.pixel, self.frame, self.dimension);
+        self.dimension += 1;
+        hash
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let low = self.next_u32() as u64;
+        let high = self.next_u32() as u64;
+        (high << 32) | low
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        for chunk in dest.chunks_mut(4) {
+            let bytes = self.next_u32().to_le_bytes();
+            chunk.copy_from_slice(&bytes[..chunk.len()]);
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand_core::Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
@@ -0,0 +1,75 @@
+//! Generators of the candidate directions, returning them together with
+//! the PDF that [`ReservoirBuilder::stream`](crate::ReservoirBuilder::stream) expects.
+//!
+//! The directions are in the local space of the surface, with the normal
+//! along the last axis. The 2D variants cover the upper half of the circle,
+//! unless noted otherwise, with the PDF in the angle measure, and the 3D variants cover the upper
+//! hemisphere, with the PDF in the solid angle measure.
+
+use rand::Rng;
+use std::f32::consts::PI;
+
+/// Uniformly distributed direction in the upper half of the circle.
+pub fn uniform_semicircle<R: Rng>(random: &mut R) -> ([f32; 2], f32) {
+    let alpha = random.gen_range(0.0..PI);
+    ([alpha.cos(), alpha.sin()], 1.0 / PI)
+}
+
+/// Uniformly distributed direction on the whole circle.
+pub fn uniform_circle<R: Rng>(random: &mut R) -> ([f32; 2], f32) {
+    let alpha = random.gen_range(0.0..2.0 * PI);
+    ([alpha.cos(), alpha.sin()], 0.5 / PI)
+}
+
+/// Direction in the upper half of the circle, distributed by the cosine to the normal.
+pub fn cosine_semicircle<R: Rng>(random: &mut R) -> ([f32; 2], f32) {
+    let x = random.gen_range(-1.0f32..1.0);
+    let y = (1.0 - x * x).sqrt();
+    ([x, y], 0.5 * y)
+}
+
+/// Uniformly distributed direction in the upper hemisphere.
+pub fn uniform_hemisphere<R: Rng>(random: &mut R) -> ([f32; 3], f32) {
+    let z = random.gen::<f32>();
+    let radius = (1.0 - z * z).sqrt();
+    let phi = random.gen_range(0.0..2.0 * PI);
+    ([radius * phi.cos(), radius * phi.sin(), z], 0.5 / PI)
+}
+
+/// Direction in the upper hemisphere, distributed by the cosine to the normal.
+pub fn cosine_hemisphere<R: Rng>(random: &mut R) -> ([f32; 3], f32) {
+    let radius = random.gen::<f32>().sqrt();
+    let phi = random.gen_range(0.0..2.0 * PI);
+    let z = (1.0 - radius * radius).sqrt();
+    ([radius * phi.cos(), radius * phi.sin(), z], z / PI)
+}
+
+/// Reflection of the `view` direction (pointing away from the surface)
+/// about a microfacet normal distributed by GGX with the given roughness `alpha`.
+///
+/// Returns `None` if the reflected direction ends up below the surface.
+pub fn ggx_reflection<R: Rng>(
+    view: [f32; 3],
+    alpha: f32,
+    random: &mut R,
+) -> Option<([f32; 3], f32)> {
+    let dot = |a: [f32; 3], b: [f32; 3]| a[0] * b[0] + a[1] * b[1] + a[2] * b[2];
+    let alpha2 = alpha * alpha;
+    // sample the half vector proportionally to D(h) * cos(h)
+    let u = random.gen::<f32>();
+    let cos2_theta = (1.0 - u) / (1.0 + (alpha2 - 1.0) * u);
+    let cos_theta = cos2_theta.sqrt();
+    let sin_theta = (1.0 - cos2_theta).max(0.0).sqrt();
+    let phi = random.gen_range(0.0..2.0 * PI);
+    let half = [sin_theta * phi.cos(), sin_theta * phi.sin(), cos_theta];
+
+    let view_dot_half = dot(view, half);
+    let dir = [0, 1, 2].map(|i| 2.0 * view_dot_half * half[i] - view[i]);
+    if dir[2] <= 0.0 || view_dot_half <= 0.0 {
+        return None;
+    }
+    let denom = cos2_theta * (alpha2 - 1.0) + 1.0;
+    let ndf = alpha2 / (PI * denom * denom);
+    // convert from the half vector measure into the reflected direction measure
+    Some((dir, ndf * cos_theta / (4.0 * view_dot_half)))
+}
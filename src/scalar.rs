@@ -0,0 +1,97 @@
+//! Floating point scalars of the reservoir weights.
+
+use crate::RandomSource;
+use std::{fmt, ops};
+
+mod private {
+    pub trait Sealed {}
+    impl Sealed for f32 {}
+    impl Sealed for f64 {}
+}
+
+/// Scalar type of the reservoir weights, either `f32` or `f64`.
+///
+/// `f32` matches the GPU and is the default. `f64` keeps the weight sum
+/// from drifting when streaming millions of candidates, e.g. in offline renders.
+pub trait Scalar:
+    private::Sealed
+    + Copy
+    + Default
+    + fmt::Debug
+    + PartialOrd
+    + ops::Add<Output = Self>
+    + ops::Sub<Output = Self>
+    + ops::Mul<Output = Self>
+    + ops::Div<Output = Self>
+{
+    /// Zero value.
+    const ZERO: Self;
+    /// Positive infinity.
+    const INFINITY: Self;
+    /// Convert from `f32`.
+    fn from_f32(value: f32) -> Self;
+    /// Convert to `f32`, possibly losing precision.
+    fn to_f32(self) -> f32;
+    /// Convert from an integer count.
+    fn from_u32(value: u32) -> Self;
+    /// Convert from a large integer count, possibly losing precision.
+    fn from_u64(value: u64) -> Self;
+    /// Absolute value.
+    fn abs(self) -> Self;
+    /// Check if the value is neither infinite nor NaN.
+    fn is_finite(self) -> bool;
+    /// Generate a uniform random value in [0, 1).
+    fn gen_unit<R: RandomSource + ?Sized>(random: &mut R) -> Self;
+}
+
+impl Scalar for f32 {
+    const ZERO: Self = 0.0;
+    const INFINITY: Self = f32::INFINITY;
+    fn from_f32(value: f32) -> Self {
+        value
+    }
+    fn to_f32(self) -> f32 {
+        self
+    }
+    fn from_u32(value: u32) -> Self {
+        value as f32
+    }
+    fn from_u64(value: u64) -> Self {
+        value as f32
+    }
+    fn abs(self) -> Self {
+        self.abs()
+    }
+    fn is_finite(self) -> bool {
+        self.is_finite()
+    }
+    fn gen_unit<R: RandomSource + ?Sized>(random: &mut R) -> Self {
+        random.uniform()
+    }
+}
+
+impl Scalar for f64 {
+    const ZERO: Self = 0.0;
+    const INFINITY: Self = f64::INFINITY;
+    fn from_f32(value: f32) -> Self {
+        value as f64
+    }
+    fn to_f32(self) -> f32 {
+        self as f32
+    }
+    fn from_u32(value: u32) -> Self {
+        value as f64
+    }
+    fn from_u64(value: u64) -> Self {
+        value as f64
+    }
+    fn abs(self) -> Self {
+        self.abs()
+    }
+    fn is_finite(self) -> bool {
+        self.is_finite()
+    }
+    fn gen_unit<R: RandomSource + ?Sized>(random: &mut R) -> Self {
+        random.uniform_f64()
+    }
+}
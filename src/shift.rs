@@ -0,0 +1,119 @@
+//! Shift mapping utilities for reusing samples across domains,
+//! generic over the dimension of the world.
+
+use core::array;
+
+/// Minimal vector interface needed by the shift mapping.
+///
+/// Implemented for `[f32; N]` arrays, and for `glam` vectors
+/// with the "glam" feature enabled.
+pub trait Vector: Copy {
+    /// Number of the dimensions of the world.
+    const DIMENSION: usize;
+    /// Return the sum with another vector multiplied by a scalar.
+    fn add_scaled(self, other: Self, scale: f32) -> Self;
+    /// Return the dot product.
+    fn dot(self, other: Self) -> f32;
+
+    /// Return the length.
+    fn length(self) -> f32 {
+        self.dot(self).sqrt()
+    }
+}
+
+impl<const N: usize> Vector for [f32; N] {
+    const DIMENSION: usize = N;
+    fn add_scaled(self, other: Self, scale: f32) -> Self {
+        array::from_fn(|i| self[i] + other[i] * scale)
+    }
+    fn dot(self, other: Self) -> f32 {
+        self.iter().zip(other.iter()).map(|(a, b)| a * b).sum()
+    }
+}
+
+#[cfg(feature = "glam")]
+macro_rules! impl_glam_vector {
+    ($ty:ty, $dimension:expr) => {
+        impl Vector for $ty {
+            const DIMENSION: usize = $dimension;
+            fn add_scaled(self, other: Self, scale: f32) -> Self {
+                self + other * scale
+            }
+            fn dot(self, other: Self) -> f32 {
+                <$ty>::dot(self, other)
+            }
+        }
+    };
+}
+
+#[cfg(feature = "glam")]
+impl_glam_vector!(glam::Vec2, 2);
+#[cfg(feature = "glam")]
+impl_glam_vector!(glam::Vec3, 3);
+#[cfg(feature = "glam")]
+impl_glam_vector!(glam::Vec3A, 3);
+
+/// Direction and distance from a receiver to a vertex.
+#[derive(Clone, Copy, Debug)]
+pub struct Connection<V> {
+    /// Normalized direction towards the vertex.
+    pub dir: V,
+    /// Distance to the vertex.
+    pub distance: f32,
+}
+
+impl<V: Vector> Connection<V> {
+    /// Connect a receiver to a vertex.
+    pub fn new(origin: V, vertex: V) -> Self {
+        let diff = vertex.add_scaled(origin, -1.0);
+        let distance = diff.length();
+        Self {
+            dir: diff.add_scaled(diff, 1.0 / distance - 1.0),
+            distance,
+        }
+    }
+
+    /// Return the vertex at the end of the connection.
+    pub fn vertex(&self, origin: V) -> V {
+        origin.add_scaled(self.dir, self.distance)
+    }
+}
+
+/// Reconnection shift: find the connection from another receiver
+/// to the same vertex as the given connection from the source receiver.
+pub fn reconnect<V: Vector>(
+    src_origin: V,
+    connection: &Connection<V>,
+    dst_origin: V,
+) -> Connection<V> {
+    Connection::new(dst_origin, connection.vertex(src_origin))
+}
+
+/// Jacobian of the reconnection shift, converting the solid angle
+/// (or the plane angle, in 2D) density of the source into the destination.
+///
+/// The `vertex_normal` should be provided for surface vertices, and omitted
+/// for points that look the same from all directions, such as the point lights.
+pub fn reconnection_jacobian<V: Vector>(
+    src: &Connection<V>,
+    dst: &Connection<V>,
+    vertex_normal: Option<V>,
+) -> f32 {
+    // the angle subtended by a vertex falls off with the distance to the power of
+    // the dimension minus one: linearly in 2D, quadratically in 3D
+    let exponent = V::DIMENSION as i32 - 1;
+    let distance_ratio = (src.distance / dst.distance).powi(exponent);
+    let cosine_ratio = match vertex_normal {
+        Some(normal) => {
+            let src_cos = src.dir.dot(normal).abs();
+            let dst_cos = dst.dir.dot(normal).abs();
+            if src_cos > 0.0 {
+                dst_cos / src_cos
+            } else {
+                0.0
+            }
+        }
+        None => 1.0,
+    };
+    cosine_ratio * distance_ratio
+}
@@ -0,0 +1,97 @@
+//! Online statistics of the streamed target values.
+
+use crate::{RandomSource, ReservoirBuilder, Scalar};
+
+/// Running mean and variance of the target values, using Welford's algorithm.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct TargetStats<F = f32> {
+    count: u32,
+    mean: F,
+    /// Sum of the squared deviations from the mean.
+    m2: F,
+}
+
+impl<F: Scalar> TargetStats<F> {
+    fn add(&mut self, value: F) {
+        self.count = self.count.saturating_add(1);
+        let delta = value - self.mean;
+        self.mean = self.mean + delta / F::from_u32(self.count);
+        self.m2 = self.m2 + delta * (value - self.mean);
+    }
+
+    /// Return the number of values seen.
+    pub fn count(&self) -> u32 {
+        self.count
+    }
+
+    /// Return the mean of the values.
+    pub fn mean(&self) -> F {
+        self.mean
+    }
+
+    /// Return the variance of the values, zero if there are less than two of them.
+    pub fn variance(&self) -> F {
+        if self.count < 2 {
+            F::ZERO
+        } else {
+            self.m2 / F::from_u32(self.count - 1)
+        }
+    }
+}
+
+/// Builder of a reservoir, tracking the statistics of the streamed target values,
+/// e.g. to drive adaptive sampling or firefly clamping.
+///
+/// Created by [`ReservoirBuilder::with_stats`], so that the plain builder
+/// doesn't pay for the tracking.
+#[derive(Clone, Debug)]
+pub struct WithStats<S = (), F = f32> {
+    builder: ReservoirBuilder<S, F>,
+    stats: TargetStats<F>,
+}
+
+impl<S, F: Scalar> ReservoirBuilder<S, F> {
+    /// Track the statistics of the target values streamed from now on.
+    pub fn with_stats(self) -> WithStats<S, F> {
+        WithStats {
+            builder: self,
+            stats: TargetStats::default(),
+        }
+    }
+}
+
+impl<S, F: Scalar> WithStats<S, F> {
+    /// Stream in a new sample, see [`ReservoirBuilder::stream`].
+    pub fn stream<R: RandomSource>(
+        &mut self,
+        source_pdf: F,
+        target_value: F,
+        sample: S,
+        random: &mut R,
+    ) -> bool {
+        self.stats.add(target_value);
+        self.builder
+            .stream(source_pdf, target_value, sample, random)
+    }
+
+    /// Register a sample with zero value, see [`ReservoirBuilder::add_empty_sample`].
+    pub fn add_empty_sample(&mut self) {
+        self.stats.add(F::ZERO);
+        self.builder.add_empty_sample();
+    }
+
+    /// Return the statistics of the streamed target values.
+    pub fn stats(&self) -> &TargetStats<F> {
+        &self.stats
+    }
+
+    /// Return the underlying builder for modification, e.g. to merge other reservoirs.
+    pub fn builder_mut(&mut self) -> &mut ReservoirBuilder<S, F> {
+        &mut self.builder
+    }
+
+    /// Split into the underlying builder and the statistics.
+    pub fn into_parts(self) -> (ReservoirBuilder<S, F>, TargetStats<F>) {
+        (self.builder, self.stats)
+    }
+}
@@ -0,0 +1,32 @@
+//! Reductions of an RGB color into a scalar target value.
+//!
+//! The target function of a reservoir has to be a scalar, while the payload
+//! is often an RGB color. The closer the reduction follows the final
+//! contribution, the lower the variance of the result. Any of these can be
+//! used as `target_value` for streaming and merging, as long as the same one
+//! is used consistently across the pipeline.
+
+/// Relative luminance, with the Rec. 709 primaries (CIE Y).
+///
+/// Follows the perceived brightness, so it works best for the noise the eye notices.
+/// Strongly saturated colors, especially blue, get a small target value compared
+/// to their contribution, which shows up as colored noise.
+pub fn luminance(rgb: [f32; 3]) -> f32 {
+    0.2126 * rgb[0] + 0.7152 * rgb[1] + 0.0722 * rgb[2]
+}
+
+/// Largest of the components.
+///
+/// Never underestimates any of the channels, so the variance stays bounded for
+/// all colors, at the cost of oversampling the saturated ones.
+pub fn max_component(rgb: [f32; 3]) -> f32 {
+    rgb[0].max(rgb[1]).max(rgb[2])
+}
+
+/// Euclidean norm of the color vector.
+///
+/// Sits between the other two: treats all the channels equally,
+/// but rates the white colors higher than the saturated ones.
+pub fn norm(rgb: [f32; 3]) -> f32 {
+    (rgb[0] * rgb[0] + rgb[1] * rgb[1] + rgb[2] * rgb[2]).sqrt()
+}